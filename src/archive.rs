@@ -0,0 +1,256 @@
+use std::{
+    ffi::OsStr,
+    fmt,
+    io::{self, Read, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{MetadataExt, fchown},
+    },
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::{
+    audit::Redacted,
+    beneath::Beneath,
+    error::{Error, Result},
+    file::AuthorityFile,
+    storage::{Storage, StorageFile},
+};
+
+const MAGIC: &[u8] = b"XAUTHARC";
+const VERSION: u8 = 1;
+
+/// One authority file in an `Archive`, with the ownership it's restored with
+#[derive(Clone, PartialEq, Eq)]
+pub struct ArchivedAuthority {
+    pub path: PathBuf,
+    pub uid: u32,
+    pub gid: u32,
+    /// Permission bits
+    pub mode: u32,
+    /// The exact contents, damaged entries included
+    pub bytes: Vec<u8>,
+}
+
+// The contents hold cookies
+impl fmt::Debug for ArchivedAuthority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArchivedAuthority")
+            .field("path", &self.path)
+            .field("uid", &self.uid)
+            .field("gid", &self.gid)
+            .field("mode", &self.mode)
+            .field("bytes", &Redacted(&self.bytes))
+            .finish()
+    }
+}
+
+impl ArchivedAuthority {
+    /// Reads the file at `path`, under its lock
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut file = AuthorityFile::open(path)?;
+        let metadata = file.as_file().metadata()?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            mode: metadata.mode() & 0o7777,
+            bytes: file.snapshot()?.bytes,
+        })
+    }
+
+    /// Writes the file back, replacing what's there, then restores its mode and owner.
+    ///
+    /// Meant to run as root into directories users control, so nothing in there is trusted:
+    /// symlinks aren't followed, an existing file must be a regular one owned by `uid`,
+    /// and the contents are written to a new file moved into place. Linux only
+    pub fn restore(&self) -> Result<()> {
+        let name = self.path.file_name().ok_or(io::Error::new(
+            io::ErrorKind::InvalidFilename,
+            "path does not end with a file",
+        ))?;
+        let name = Path::new(name);
+
+        let dir = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        let storage = Beneath::open_dir(dir)?.no_symlinks(true);
+        let _lock = storage.lock(name)?;
+
+        match storage.metadata(name) {
+            Ok(metadata) if metadata.is_file() && metadata.uid() == self.uid => {}
+            Ok(metadata) => {
+                return Err(Error::UntrustedFile {
+                    path: Some(self.path.clone()),
+                    owner: metadata.uid(),
+                    mode: metadata.mode() & 0o7777,
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut temp = name.as_os_str().to_owned();
+        temp.push(format!(".restore-{}", process::id()));
+        let temp = Path::new(&temp);
+
+        // A leftover from an earlier attempt, or planted. Removing never follows symlinks
+        match storage.remove(temp) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+
+        if let Err(e) = self.write_to(&storage, temp, name) {
+            let _ = storage.remove(temp);
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    fn write_to(&self, storage: &Beneath, temp: &Path, name: &Path) -> io::Result<()> {
+        let mut file = storage.create(temp, 0o600)?.into_inner();
+        file.write_all(&self.bytes)?;
+        file.set_mode(self.mode)?;
+        fchown(&file, Some(self.uid), Some(self.gid))?;
+        file.sync_all()?;
+
+        storage.rename(temp, name)
+    }
+}
+
+/// Several users' authority files in a single blob, for backups and machine migrations.
+///
+/// The blob holds cookies in the clear: store and transfer it like the files themselves
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Archive(Vec<ArchivedAuthority>);
+
+impl Archive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, path: &Path) -> Result<()> {
+        self.0.push(ArchivedAuthority::read(path)?);
+        Ok(())
+    }
+
+    pub fn push(&mut self, authority: ArchivedAuthority) {
+        self.0.push(authority);
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, ArchivedAuthority> {
+        self.0.iter()
+    }
+
+    /// Restores every file, continuing past failures. Returns the first one
+    pub fn restore(&self) -> Result<()> {
+        let mut first_error = None;
+
+        for authority in &self.0 {
+            if let Err(e) = authority.restore() {
+                first_error.get_or_insert(e);
+            }
+        }
+
+        first_error.map_or(Ok(()), Err)
+    }
+
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let too_long = |what| io::Error::new(io::ErrorKind::InvalidInput, what);
+
+        let mut buf = MAGIC.to_vec();
+        buf.push(VERSION);
+        let count = u32::try_from(self.0.len()).map_err(|_| too_long("too many authorities"))?;
+        buf.extend_from_slice(&count.to_be_bytes());
+
+        for authority in &self.0 {
+            let path = authority.path.as_os_str().as_bytes();
+            let path_len = u16::try_from(path.len()).map_err(|_| too_long("path too long"))?;
+            let bytes_len =
+                u32::try_from(authority.bytes.len()).map_err(|_| too_long("file too large"))?;
+
+            buf.extend_from_slice(&authority.uid.to_be_bytes());
+            buf.extend_from_slice(&authority.gid.to_be_bytes());
+            buf.extend_from_slice(&authority.mode.to_be_bytes());
+            buf.extend_from_slice(&path_len.to_be_bytes());
+            buf.extend_from_slice(path);
+            buf.extend_from_slice(&bytes_len.to_be_bytes());
+            buf.extend_from_slice(&authority.bytes);
+        }
+
+        Ok(buf)
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |what| io::Error::new(io::ErrorKind::InvalidData, what);
+
+        let mut header = [0u8; 9];
+        bytes.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(invalid("not an authority archive"));
+        }
+        if header[8] != VERSION {
+            return Err(invalid("unsupported archive version"));
+        }
+
+        let count = read_u32(&mut bytes)?;
+        let mut authorities = Vec::new();
+
+        for _ in 0..count {
+            let uid = read_u32(&mut bytes)?;
+            let gid = read_u32(&mut bytes)?;
+            let mode = read_u32(&mut bytes)?;
+
+            let mut path_len = [0u8; 2];
+            bytes.read_exact(&mut path_len)?;
+            let path = read_bytes(&mut bytes, u16::from_be_bytes(path_len) as usize)?;
+
+            let len = read_u32(&mut bytes)?;
+            let contents = read_bytes(&mut bytes, len as usize)?;
+
+            authorities.push(ArchivedAuthority {
+                path: PathBuf::from(OsStr::from_bytes(&path)),
+                uid,
+                gid,
+                mode,
+                bytes: contents,
+            });
+        }
+
+        if !bytes.is_empty() {
+            return Err(invalid("trailing data after the archive"));
+        }
+
+        Ok(Self(authorities))
+    }
+}
+
+impl IntoIterator for Archive {
+    type Item = ArchivedAuthority;
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+fn read_u32(reader: &mut &[u8]) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_bytes(reader: &mut &[u8], len: usize) -> io::Result<Vec<u8>> {
+    // Check against what's left first, a corrupt length mustn't trigger a huge allocation
+    if len > reader.len() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    let (bytes, rest) = reader.split_at(len);
+    *reader = rest;
+    Ok(bytes.to_vec())
+}
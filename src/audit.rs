@@ -0,0 +1,61 @@
+use std::{
+    fmt, io,
+    sync::{Arc, RwLock},
+};
+
+/// How a credential is leaving the library's hands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SecretAccess {
+    /// Encoded into a writer, e.g. the authority file
+    Serialize,
+    /// Handed to the caller by `Cookie::raw_data` or formatted as hex. Can't be vetoed
+    RawData,
+    /// Sent to another process by a `Broker`
+    Brokered,
+}
+
+pub trait SecretAccessObserver: Send + Sync {
+    /// Return false to veto the access, where possible
+    fn on_access(&self, access: SecretAccess, auth_name: &str) -> bool;
+}
+
+static OBSERVER: RwLock<Option<Arc<dyn SecretAccessObserver>>> = RwLock::new(None);
+
+/// Process-wide, as credentials can be exposed from anywhere in the library
+pub fn set_secret_access_observer(observer: Option<Arc<dyn SecretAccessObserver>>) {
+    *OBSERVER.write().unwrap_or_else(|e| e.into_inner()) = observer;
+}
+
+fn allowed(access: SecretAccess, auth_name: &str) -> bool {
+    let observer = OBSERVER.read().unwrap_or_else(|e| e.into_inner());
+
+    match observer.as_ref() {
+        Some(observer) => observer.on_access(access, auth_name),
+        None => true,
+    }
+}
+
+/// For accesses that can't be vetoed
+pub(crate) fn notify(access: SecretAccess, auth_name: &str) {
+    allowed(access, auth_name);
+}
+
+pub(crate) fn check(access: SecretAccess, auth_name: &str) -> io::Result<()> {
+    match allowed(access, auth_name) {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("access to {auth_name} data vetoed by observer"),
+        )),
+    }
+}
+
+/// Debug output for secret bytes: only their length, like `Cookie`'s Display
+pub(crate) struct Redacted<'a>(pub &'a [u8]);
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{} bytes>", self.0.len())
+    }
+}
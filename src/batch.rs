@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use crate::{Authority, Cookie, Scope, Target, encoding::Entry, server::ServerAuthBuilder};
+
+struct Display {
+    number: u32,
+    cookie: Cookie,
+    scopes: Vec<Scope>,
+}
+
+/// Cookies generated for many displays at once, see `Authority::generate_batch`
+pub struct Batch {
+    /// Client entries for every display and scope
+    pub authority: Authority,
+    displays: Vec<Display>,
+}
+
+impl Batch {
+    fn display(&self, number: u32) -> Option<&Display> {
+        self.displays
+            .iter()
+            .find(|display| display.number == number)
+    }
+
+    pub fn cookie(&self, display: u32) -> Option<&Cookie> {
+        self.display(display).map(|display| &display.cookie)
+    }
+
+    /// The displays in order of first appearance in the spec
+    pub fn displays(&self) -> impl Iterator<Item = u32> + '_ {
+        self.displays.iter().map(|display| display.number)
+    }
+
+    /// What the server for `display` is started with: its cookie for each of its scopes,
+    /// in slots of their own
+    pub fn server(&self, display: u32) -> Option<ServerAuthBuilder> {
+        let display = self.display(display)?;
+
+        Some(
+            display
+                .scopes
+                .iter()
+                .fold(ServerAuthBuilder::new(), |builder, scope| {
+                    builder.cookie(&display.cookie, scope.clone())
+                }),
+        )
+    }
+}
+
+impl Authority {
+    /// One cookie per display in `spec`, drawn from `rng`, with a client entry
+    /// for each scope it's listed with. For lab and thin client provisioning,
+    /// seeding many authorities per boot
+    pub fn generate_batch(
+        spec: &[(Scope, u32)],
+        mut rng: impl FnMut() -> [u8; Cookie::BYTES_LEN],
+    ) -> Batch {
+        let mut displays: Vec<Display> = Vec::new();
+        let mut index = HashMap::new();
+        let mut entries = Vec::with_capacity(spec.len());
+
+        for (scope, number) in spec {
+            let i = *index.entry(*number).or_insert_with(|| {
+                displays.push(Display {
+                    number: *number,
+                    cookie: Cookie::new(rng()),
+                    scopes: Vec::new(),
+                });
+                displays.len() - 1
+            });
+
+            let display = &mut displays[i];
+            if display.scopes.contains(scope) {
+                continue;
+            }
+
+            display.scopes.push(scope.clone());
+            entries.push(Entry::new(
+                &display.cookie,
+                scope.clone(),
+                Target::Client {
+                    display_number: number.to_string(),
+                },
+            ));
+        }
+
+        Batch {
+            authority: Authority::new(Some(entries)),
+            displays,
+        }
+    }
+}
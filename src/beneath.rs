@@ -0,0 +1,464 @@
+use std::{
+    ffi::{CStr, CString, OsStr, c_char},
+    fs::{File, Metadata},
+    io::{self, Read, Seek, Write},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::{ffi::OsStrExt, fs::MetadataExt},
+    },
+    path::{Component, Path},
+    process,
+    sync::Arc,
+    time::SystemTime,
+};
+
+use crate::{
+    error::Result,
+    file::AuthorityFile,
+    lock::{LockGuard, holder_record, links_unsupported, lock_paths},
+    storage::{Storage, StorageFile},
+};
+
+unsafe extern "C" {
+    fn syscall(number: i64, ...) -> i64;
+    fn renameat(
+        olddirfd: i32,
+        oldpath: *const c_char,
+        newdirfd: i32,
+        newpath: *const c_char,
+    ) -> i32;
+    fn unlinkat(dirfd: i32, path: *const c_char, flags: i32) -> i32;
+    fn linkat(
+        olddirfd: i32,
+        oldpath: *const c_char,
+        newdirfd: i32,
+        newpath: *const c_char,
+        flags: i32,
+    ) -> i32;
+    fn mkdirat(dirfd: i32, path: *const c_char, mode: u32) -> i32;
+}
+
+#[repr(C)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+// The number is shared by all architectures, being newer than the unified table
+#[cfg(target_os = "linux")]
+const SYS_OPENAT2: Option<i64> = Some(437);
+#[cfg(not(target_os = "linux"))]
+const SYS_OPENAT2: Option<i64> = None;
+
+const ELOOP: i32 = 40;
+
+const RESOLVE_NO_MAGICLINKS: u64 = 0x02;
+const RESOLVE_NO_SYMLINKS: u64 = 0x04;
+const RESOLVE_BENEATH: u64 = 0x08;
+
+const O_RDONLY: u64 = 0;
+const O_RDWR: u64 = 0o2;
+const O_CREAT: u64 = 0o100;
+const O_EXCL: u64 = 0o200;
+const O_CLOEXEC: u64 = 0o2000000;
+const O_PATH: u64 = 0o10000000;
+#[cfg(not(target_arch = "aarch64"))]
+const O_DIRECTORY: u64 = 0o200000;
+#[cfg(target_arch = "aarch64")]
+const O_DIRECTORY: u64 = 0o40000;
+
+/// Storage confined to a directory the caller trusts, like the user's runtime dir,
+/// for brokers that must not be led elsewhere by crafted paths.
+///
+/// Paths are relative to the directory and resolved with `openat2(2)` and
+/// `RESOLVE_BENEATH`, so `..` and absolute symlinks can't escape it. Lock files
+/// are created beneath it too. Linux 5.6 or later
+#[derive(Debug)]
+pub struct Beneath {
+    dir: Arc<OwnedFd>,
+    resolve: u64,
+}
+
+impl Beneath {
+    pub fn new(dir: OwnedFd) -> Self {
+        Self {
+            dir: Arc::new(dir),
+            resolve: RESOLVE_BENEATH | RESOLVE_NO_MAGICLINKS,
+        }
+    }
+
+    /// Confined to the directory at `path`, itself resolved normally
+    pub fn open_dir(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(File::open(path)?.into()))
+    }
+
+    /// Refuses symlinks anywhere in the path, even ones staying inside the directory
+    pub fn no_symlinks(mut self, no_symlinks: bool) -> Self {
+        match no_symlinks {
+            true => self.resolve |= RESOLVE_NO_SYMLINKS,
+            false => self.resolve &= !RESOLVE_NO_SYMLINKS,
+        }
+
+        self
+    }
+
+    fn openat2(&self, path: &Path, flags: u64, mode: u32) -> io::Result<File> {
+        openat2(self.dir.as_raw_fd(), path, flags, mode, self.resolve)
+    }
+
+    /// Of `path` itself, without opening it for reading
+    pub(crate) fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.openat2(path, O_PATH, 0)?.metadata()
+    }
+
+    fn file(&self, file: File) -> BeneathFile {
+        BeneathFile {
+            file,
+            dir: self.dir.clone(),
+            resolve: self.resolve,
+        }
+    }
+
+    /// The resolved parent of `path` and its last component, for the *at calls
+    /// that don't take resolve flags. None of them follow a trailing symlink
+    fn parent(&self, path: &Path) -> io::Result<(File, CString)> {
+        let Some(Component::Normal(name)) = path.components().next_back() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidFilename,
+                "path does not end with a file",
+            ));
+        };
+
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        let dir = self.openat2(parent, O_PATH | O_DIRECTORY, 0)?;
+        Ok((dir, c_path(Path::new(name))?))
+    }
+}
+
+fn openat2(dir: RawFd, path: &Path, flags: u64, mode: u32, resolve: u64) -> io::Result<File> {
+    let number = SYS_OPENAT2
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "openat2 is Linux only"))?;
+
+    let path = c_path(path)?;
+    let how = OpenHow {
+        flags: flags | O_CLOEXEC,
+        mode: mode as u64,
+        resolve,
+    };
+
+    let fd = unsafe {
+        syscall(
+            number,
+            dir,
+            path.as_ptr(),
+            &how as *const OpenHow,
+            size_of::<OpenHow>(),
+        )
+    };
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul byte"))
+}
+
+fn check(ret: i32) -> io::Result<()> {
+    match ret {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// A file opened through `Beneath`, reopened beneath the same directory when it goes stale
+#[derive(Debug)]
+pub struct BeneathFile {
+    file: File,
+    dir: Arc<OwnedFd>,
+    resolve: u64,
+}
+
+impl BeneathFile {
+    pub fn into_inner(self) -> File {
+        self.file
+    }
+}
+
+impl AsFd for BeneathFile {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+impl Read for BeneathFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for BeneathFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for BeneathFile {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl StorageFile for BeneathFile {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.file.set_len(len)
+    }
+
+    fn is_regular(&self) -> io::Result<bool> {
+        self.file.is_regular()
+    }
+
+    fn set_mode(&mut self, mode: u32) -> io::Result<()> {
+        self.file.set_mode(mode)
+    }
+
+    fn modified(&self) -> io::Result<Option<SystemTime>> {
+        self.file.modified()
+    }
+
+    fn reopen(&self, path: &Path) -> io::Result<Option<Self>> {
+        let file = openat2(self.dir.as_raw_fd(), path, O_RDWR, 0, self.resolve)?;
+
+        Ok(Some(Self {
+            file,
+            dir: self.dir.clone(),
+            resolve: self.resolve,
+        }))
+    }
+}
+
+impl Storage for Beneath {
+    type File = BeneathFile;
+    type Lock = BeneathLock;
+
+    fn open(&self, path: &Path) -> io::Result<BeneathFile> {
+        Ok(self.file(self.openat2(path, O_RDWR, 0)?))
+    }
+
+    fn open_read_only(&self, path: &Path) -> io::Result<BeneathFile> {
+        Ok(self.file(self.openat2(path, O_RDONLY, 0)?))
+    }
+
+    fn create(&self, path: &Path, mode: u32) -> io::Result<BeneathFile> {
+        Ok(self.file(self.openat2(path, O_RDWR | O_CREAT | O_EXCL, mode)?))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from_dir, from) = self.parent(from)?;
+        let (to_dir, to) = self.parent(to)?;
+
+        check(unsafe {
+            renameat(
+                from_dir.as_raw_fd(),
+                from.as_ptr(),
+                to_dir.as_raw_fd(),
+                to.as_ptr(),
+            )
+        })
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let (dir, name) = self.parent(path)?;
+        check(unsafe { unlinkat(dir.as_raw_fd(), name.as_ptr(), 0) })
+    }
+
+    fn lock(&self, path: &Path) -> io::Result<BeneathLock> {
+        let (creat_path, link_path) = lock_paths(path)?;
+
+        let mut lockfile = self.openat2(&creat_path, O_RDWR | O_CREAT | O_EXCL, 0o600)?;
+        let creat_path = self.parent(&creat_path)?;
+
+        let record = holder_record();
+        if let Err(e) = lockfile.write_all(&record) {
+            unsafe { unlinkat(creat_path.0.as_raw_fd(), creat_path.1.as_ptr(), 0) };
+            return Err(e);
+        }
+        let metadata = lockfile.metadata()?;
+        drop(lockfile);
+
+        let link_path = self.parent(&link_path)?;
+
+        let linked = check(unsafe {
+            linkat(
+                creat_path.0.as_raw_fd(),
+                creat_path.1.as_ptr(),
+                link_path.0.as_raw_fd(),
+                link_path.1.as_ptr(),
+                0,
+            )
+        });
+
+        let linked = match linked {
+            Ok(()) => true,
+            Err(e) if links_unsupported(&e) => false,
+            Err(e) => {
+                // The link may be someone else's, only our creat file goes
+                unsafe { unlinkat(creat_path.0.as_raw_fd(), creat_path.1.as_ptr(), 0) };
+                return Err(e);
+            }
+        };
+
+        Ok(BeneathLock {
+            creat_path,
+            link_path,
+            inode: (metadata.dev(), metadata.ino()),
+            record,
+            linked,
+        })
+    }
+
+    fn create_dir_all(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let mut prefix = Path::new("").to_path_buf();
+
+        for component in path.components() {
+            prefix.push(component);
+
+            let Component::Normal(_) = component else {
+                continue;
+            };
+
+            let (dir, name) = self.parent(&prefix)?;
+            match check(unsafe { mkdirat(dir.as_raw_fd(), name.as_ptr(), mode) }) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                result => result?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A `-c`/`-l` lock made through `Beneath`
+pub struct BeneathLock {
+    creat_path: (File, CString),
+    link_path: (File, CString),
+    // (dev, ino) and contents of the lock files we created, as in `Lock`
+    inode: (u64, u64),
+    record: Vec<u8>,
+    // As in `Lock`
+    linked: bool,
+}
+
+impl BeneathLock {
+    fn files(&self) -> impl Iterator<Item = &(File, CString)> {
+        [&self.creat_path]
+            .into_iter()
+            .chain(self.linked.then_some(&self.link_path))
+    }
+
+    fn is_ours(&self, dir: &File, name: &CStr) -> io::Result<bool> {
+        let name = Path::new(OsStr::from_bytes(name.to_bytes()));
+        let resolve = RESOLVE_BENEATH | RESOLVE_NO_SYMLINKS;
+
+        let mut file = match openat2(dir.as_raw_fd(), name, O_RDONLY, 0, resolve) {
+            Ok(file) => file,
+            // A symlink wasn't made by us either
+            Err(e) if e.kind() == io::ErrorKind::NotFound || e.raw_os_error() == Some(ELOOP) => {
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let metadata = file.metadata()?;
+        let mut record = Vec::new();
+        file.read_to_end(&mut record)?;
+
+        Ok((metadata.dev(), metadata.ino()) == self.inode && record == self.record)
+    }
+
+    // Like `remove_if_same` for `Lock`: moved aside, then removed if ours or moved back
+    fn release(&self, dir: &File, name: &CString) -> io::Result<()> {
+        let mut aside = name.as_bytes().to_vec();
+        aside.extend(format!(".{}", process::id()).bytes());
+        let aside = CString::new(aside)?;
+
+        let dir_fd = dir.as_raw_fd();
+        check(unsafe { renameat(dir_fd, name.as_ptr(), dir_fd, aside.as_ptr()) })?;
+
+        if !self.is_ours(dir, &aside)? {
+            match check(unsafe { linkat(dir_fd, aside.as_ptr(), dir_fd, name.as_ptr(), 0) }) {
+                Err(e) if links_unsupported(&e) => {
+                    return check(unsafe {
+                        renameat(dir_fd, aside.as_ptr(), dir_fd, name.as_ptr())
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        check(unsafe { unlinkat(dir_fd, aside.as_ptr(), 0) })
+    }
+}
+
+impl LockGuard for BeneathLock {
+    fn still_held(&self) -> io::Result<bool> {
+        for (dir, name) in self.files() {
+            if !self.is_ours(dir, name)? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl Drop for BeneathLock {
+    fn drop(&mut self) {
+        // Files that aren't ours anymore belong to whoever broke the lock and took it since
+        for (dir, name) in self.files() {
+            let _ = self.release(dir, name);
+        }
+    }
+}
+
+impl AuthorityFile<Beneath> {
+    /// Opens `path` and takes its lock up front, creating it privately if missing.
+    /// Everything after that works relative to the directory descriptor opened here,
+    /// so daemons can restrict filesystem access to that directory before handling
+    /// untrusted input. Writes happen in place, there is no temp file.
+    ///
+    /// What remains needed, in Landlock terms:
+    /// - read, write and truncate on the authority. These also cover reopening it
+    ///   by name, when its handle goes stale
+    /// - read on the lock files, to check they are still ours in `still_held`
+    ///   and before releasing them
+    /// - remove-file, make-reg and refer on the directory: releasing moves each
+    ///   lock file aside, then removes it, or links it back if it isn't ours
+    pub fn pre_open(path: &Path) -> Result<Self> {
+        let name = path.file_name().ok_or(io::Error::new(
+            io::ErrorKind::InvalidFilename,
+            "path does not end with a file",
+        ))?;
+
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        let storage = Beneath::open_dir(dir)?.no_symlinks(true);
+        Self::open_or_create_in(&storage, Path::new(name))
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    audit::{self, Redacted, SecretAccess},
+    display::DisplayName,
+    error::Result,
+    file::AuthorityFile,
+    lookup::DEFAULT_PROTOCOLS,
+    retry::RetryPolicy,
+    storage::{Fs, Storage},
+};
+
+/// D-Bus names of the broker, for wiring `Broker::handle` into a bus connection.
+/// `GetCookie` takes `(s display, as protocols)` and returns `(s auth_name, ay auth_data)`
+pub const BUS_NAME: &str = "io.github.hazyfossa.XAuthority1";
+pub const OBJECT_PATH: &str = "/io/github/hazyfossa/XAuthority1";
+pub const INTERFACE: &str = "io.github.hazyfossa.XAuthority1";
+pub const GET_COOKIE: &str = "GetCookie";
+
+/// "Cookie for display N", sent by session components to the broker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookieRequest {
+    pub display: String,
+    /// Most preferred first. Empty means `DEFAULT_PROTOCOLS`
+    pub protocols: Vec<String>,
+}
+
+impl CookieRequest {
+    pub fn new(display: &DisplayName) -> Self {
+        Self {
+            display: display.to_string(),
+            protocols: Vec::new(),
+        }
+    }
+
+    pub fn protocols(mut self, protocols: &[&str]) -> Self {
+        self.protocols = protocols
+            .iter()
+            .map(|protocol| protocol.to_string())
+            .collect();
+        self
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct CookieReply {
+    pub auth_name: String,
+    pub auth_data: Vec<u8>,
+}
+
+impl fmt::Debug for CookieReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CookieReply")
+            .field("auth_name", &self.auth_name)
+            .field("auth_data", &Redacted(&self.auth_data))
+            .finish()
+    }
+}
+
+type Policy = dyn Fn(u32, &DisplayName) -> bool + Send + Sync;
+
+/// The privileged side: owns the authority file and answers `CookieRequest`s.
+///
+/// The transport is up to the caller, who must pass the uid the bus reports for the sender
+pub struct Broker<S: Storage = Fs> {
+    storage: S,
+    path: PathBuf,
+    policy: Box<Policy>,
+    retry: RetryPolicy,
+}
+
+impl Broker {
+    /// `policy` decides whether the caller with the given uid may have the cookie for a display
+    pub fn new(
+        path: &Path,
+        policy: impl Fn(u32, &DisplayName) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self::new_in(Fs::default(), path, policy)
+    }
+}
+
+impl<S: Storage> Broker<S> {
+    pub fn new_in(
+        storage: S,
+        path: &Path,
+        policy: impl Fn(u32, &DisplayName) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            storage,
+            path: path.to_path_buf(),
+            policy: Box::new(policy),
+            retry: RetryPolicy::fixed(10, Duration::from_millis(100)),
+        }
+    }
+
+    /// How long a request waits for writers to release the file's lock.
+    /// Defaults to 10 attempts, 100ms apart
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The reply to `request` from `caller_uid`, or `None` if there's no entry for the display.
+    /// Denied requests fail with `PermissionDenied`, as do ones vetoed by the observer
+    pub fn handle(&self, caller_uid: u32, request: &CookieRequest) -> Result<Option<CookieReply>> {
+        let display: DisplayName = request.display.parse()?;
+
+        if !(self.policy)(caller_uid, &display) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("uid {caller_uid} may not access display {display}"),
+            )
+            .into());
+        }
+
+        let protocols: Vec<&str> = match request.protocols.is_empty() {
+            true => DEFAULT_PROTOCOLS.to_vec(),
+            false => request.protocols.iter().map(String::as_str).collect(),
+        };
+
+        // Locked only for the read, so requests don't hold up other writers
+        let authority =
+            AuthorityFile::open_with_retry_in(&self.storage, &self.path, self.retry)?.get()?;
+
+        let Some(entry) = authority.get_auth_for_display_with(&display, &protocols)? else {
+            return Ok(None);
+        };
+
+        audit::check(SecretAccess::Brokered, &entry.auth_name)?;
+
+        Ok(Some(CookieReply {
+            auth_name: entry.auth_name.to_string(),
+            auth_data: entry.auth_data_unobserved().to_vec(),
+        }))
+    }
+}
@@ -0,0 +1,85 @@
+use std::{fmt, sync::Arc};
+
+use crate::{
+    audit::Redacted,
+    encoding::{Entry, Family, shared_name},
+    error::{Error, Result},
+};
+
+/// Builds entries field by field, for those `Entry::new` can't express.
+/// Fields are validated by `build`
+#[derive(Clone, Default)]
+pub struct EntryBuilder {
+    family: Option<Family>,
+    address: Vec<u8>,
+    display_number: String,
+    auth_name: Option<Arc<str>>,
+    auth_data: Vec<u8>,
+}
+
+impl fmt::Debug for EntryBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EntryBuilder")
+            .field("family", &self.family)
+            .field("address", &self.address)
+            .field("display_number", &self.display_number)
+            .field("auth_name", &self.auth_name)
+            .field("auth_data", &Redacted(&self.auth_data))
+            .finish()
+    }
+}
+
+impl Entry {
+    pub fn builder() -> EntryBuilder {
+        EntryBuilder::default()
+    }
+}
+
+impl EntryBuilder {
+    pub fn family(mut self, family: Family) -> Self {
+        self.family = Some(family);
+        self
+    }
+
+    pub fn address(mut self, address: impl Into<Vec<u8>>) -> Self {
+        self.address = address.into();
+        self
+    }
+
+    /// Leave unset to match any display
+    pub fn display(mut self, display_number: impl ToString) -> Self {
+        self.display_number = display_number.to_string();
+        self
+    }
+
+    /// The auth protocol name, e.g. `MIT-MAGIC-COOKIE-1`
+    pub fn protocol(mut self, name: &str) -> Self {
+        self.auth_name = Some(shared_name(name));
+        self
+    }
+
+    pub fn data(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.auth_data = data.into();
+        self
+    }
+
+    pub fn build(self) -> Result<Entry> {
+        let family = self
+            .family
+            .ok_or(Error::InvalidEntry("family is not set"))?;
+        let auth_name = self
+            .auth_name
+            .ok_or(Error::InvalidEntry("protocol is not set"))?;
+
+        let entry = Entry::from_parts(
+            family,
+            self.address,
+            self.display_number,
+            auth_name,
+            self.auth_data,
+        );
+
+        entry.validate()?;
+        Ok(entry)
+    }
+}
@@ -0,0 +1,51 @@
+use std::borrow::Cow;
+
+use crate::encoding::{Entry, Family};
+
+/// Address some old tools wrote for FamilyWild entries
+const LEGACY_WILD_ADDRESS: [u8; 4] = [127, 0, 0, 2];
+
+/// Deviations from the standard encoding, for interop with legacy readers
+#[derive(Debug, Clone, Default)]
+pub struct CompatOptions {
+    legacy_wild: bool,
+}
+
+impl CompatOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write FamilyWild entries with a dummy `127.0.0.2` address instead of an empty one,
+    /// for parsers written against old examples. The address is ignored either way,
+    /// and dropped again when reading through the same `AuthorityFile`
+    pub fn legacy_wild_address(mut self, enabled: bool) -> Self {
+        self.legacy_wild = enabled;
+        self
+    }
+
+    /// `entry` as it should be written
+    pub(crate) fn apply<'a>(&self, entry: &'a Entry) -> Cow<'a, Entry> {
+        if self.legacy_wild && entry.family == Family::Wild && entry.address.is_empty() {
+            let mut entry = entry.clone();
+            entry.address = LEGACY_WILD_ADDRESS.to_vec();
+            return Cow::Owned(entry);
+        }
+
+        Cow::Borrowed(entry)
+    }
+
+    /// Undoes `apply` on entries read back, so they equal the ones written.
+    /// FamilyWild entries other tools wrote with that address lose it too, which changes nothing
+    pub(crate) fn restore<'a>(&self, entries: impl IntoIterator<Item = &'a mut Entry>) {
+        if !self.legacy_wild {
+            return;
+        }
+
+        for entry in entries {
+            if entry.family == Family::Wild && entry.address == LEGACY_WILD_ADDRESS {
+                entry.address.clear();
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    fmt::{self, Write},
+    io,
+    net::{Ipv4Addr, Ipv6Addr},
+    os::unix::net::UnixStream,
+    str::FromStr,
+};
+
+use crate::{
+    encoding::{Entry, Family},
+    error::Error,
+    family_codes::{FAMILY_DECNET, FAMILY_INTERNET, FAMILY_INTERNET6},
+};
+
+/// A parsed X display name, as found in `$DISPLAY`: `[protocol/][host]:display[.screen]`.
+/// Built by parsing, or with `new` as fields may be added
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DisplayName {
+    pub protocol: Option<String>,
+    /// Empty for local displays
+    pub host: String,
+    pub display: u32,
+    /// Not part of the authority key, but needed right after the lookup to connect
+    pub screen: Option<u32>,
+}
+
+impl DisplayName {
+    /// Without a screen, see `with_screen`
+    pub fn new(protocol: Option<String>, host: String, display: u32) -> Self {
+        Self {
+            protocol,
+            host,
+            display,
+            screen: None,
+        }
+    }
+
+    pub fn with_screen(mut self, screen: u32) -> Self {
+        self.screen = Some(screen);
+        self
+    }
+
+    /// Whether clients connect over a unix socket, making the entry FamilyLocal
+    pub fn is_local(&self) -> bool {
+        match self.protocol.as_deref() {
+            Some("unix" | "local") => true,
+            Some(_) => false,
+            None => self.host.is_empty() || self.host == "unix",
+        }
+    }
+
+    /// Whether a server is listening on this local display's socket.
+    /// Always false for remote displays, which would need a network round trip to tell
+    pub fn has_local_server(&self) -> bool {
+        self.is_local() && connect_local(self.display).is_ok()
+    }
+
+    /// The screen to connect to, 0 unless given
+    pub fn screen_or_default(&self) -> u32 {
+        self.screen.unwrap_or(0)
+    }
+}
+
+/// Connects to the socket of local display `display`. On Linux, the abstract one
+/// is tried first like libxcb does, as many servers no longer create the one in /tmp
+pub(crate) fn connect_local(display: u32) -> io::Result<UnixStream> {
+    let path = format!("/tmp/.X11-unix/X{display}");
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+        let addr = SocketAddr::from_abstract_name(&path)?;
+        if let Ok(stream) = UnixStream::connect_addr(&addr) {
+            return Ok(stream);
+        }
+    }
+
+    UnixStream::connect(path)
+}
+
+impl FromStr for DisplayName {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidDisplayName(value.to_string());
+
+        let (protocol, rest) = match value.split_once('/') {
+            Some((protocol, rest)) => (Some(protocol.to_string()), rest),
+            None => (None, value),
+        };
+
+        // The host may itself contain colons (IPv6), so split on the last one
+        let (host, number) = rest.rsplit_once(':').ok_or_else(invalid)?;
+        let host = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host);
+
+        let (display, screen) = match number.split_once('.') {
+            Some((display, screen)) => (display, Some(screen.parse().map_err(|_| invalid())?)),
+            None => (number, None),
+        };
+
+        Ok(Self {
+            protocol,
+            host: host.to_string(),
+            display: display.parse().map_err(|_| invalid())?,
+            screen,
+        })
+    }
+}
+
+impl fmt::Display for DisplayName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(protocol) = &self.protocol {
+            write!(f, "{protocol}/")?;
+        }
+
+        match self.host.contains(':') {
+            true => write!(f, "[{}]:{}", self.host, self.display)?,
+            false => write!(f, "{}:{}", self.host, self.display)?,
+        }
+
+        match self.screen {
+            Some(screen) => write!(f, ".{screen}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Entry {
+    /// The display name column of `xauth list`, e.g. `host/unix:0` or `10.0.0.1:0`.
+    ///
+    /// Addresses are never resolved to host names. DECnet nodes are shown as
+    /// `area.node::display`, families without a readable form as `#family#hex#:display`
+    pub fn display_name(&self) -> String {
+        let display = &self.display_number;
+
+        match (self.family, self.address.as_slice()) {
+            (Family::Local, address) => {
+                format!("{}/unix:{display}", String::from_utf8_lossy(address))
+            }
+            (Family::Other(FAMILY_INTERNET), &[a, b, c, d]) => {
+                format!("{}:{display}", Ipv4Addr::new(a, b, c, d))
+            }
+            (Family::Other(FAMILY_INTERNET6), address)
+                if let Ok(octets) = <[u8; 16]>::try_from(address) =>
+            {
+                format!("[{}]:{display}", Ipv6Addr::from(octets))
+            }
+            // Little endian, 6 bits of area and 10 of node
+            (Family::Other(FAMILY_DECNET), &[low, high]) => {
+                let address = u16::from_le_bytes([low, high]);
+                format!("{}.{}::{display}", address >> 10, address & 0x3ff)
+            }
+            (family, address) => {
+                let mut name = format!("#{:04x}#", family.code());
+                for byte in address {
+                    let _ = write!(name, "{byte:02x}");
+                }
+                let _ = write!(name, "#:{display}");
+                name
+            }
+        }
+    }
+}
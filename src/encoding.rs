@@ -1,8 +1,21 @@
 use std::{
+    fmt,
+    hash::{Hash, Hasher},
     io::{self, Read, Write},
+    ops::Deref,
+    sync::{Arc, LazyLock},
     vec,
 };
 
+#[cfg(feature = "hardened-memory")]
+use crate::secret::SecretBuf;
+use crate::{
+    MIT_MAGIC_COOKIE_1, XDM_AUTHORIZATION_1,
+    audit::{self, Redacted, SecretAccess},
+    error::{Error, FieldTooLong, Result},
+    family_codes::{FAMILY_DECNET, FAMILY_INTERNET, FAMILY_INTERNET6, FAMILY_LOCAL, FAMILY_WILD},
+};
+
 fn read_len<R: Read>(reader: &mut R) -> io::Result<u16> {
     let mut buffer = [0u8; 2];
     reader.read_exact(&mut buffer)?;
@@ -23,6 +36,33 @@ fn read_field<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
     reader.read_exact(&mut buf).map(|_| buf)
 }
 
+fn skip_field<R: Read>(reader: &mut R) -> io::Result<()> {
+    let len = read_len(reader)? as u64;
+
+    if io::copy(&mut reader.take(len), &mut io::sink())? < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(())
+}
+
+fn encode_parts<W: Write>(
+    writer: &mut W,
+    family: Family,
+    address: &[u8],
+    display_number: &str,
+    auth_name: &str,
+    auth_data: &[u8],
+) -> io::Result<()> {
+    write_len(writer, family.code())?;
+    write_field(writer, "address", address)?;
+    write_field(writer, "display_number", display_number.as_bytes())?;
+    write_field(writer, "auth_name", auth_name.as_bytes())?;
+    write_field(writer, "auth_data", auth_data)?;
+
+    Ok(())
+}
+
 fn err_invalid_field(field: &str) -> io::Error {
     io::Error::new(
         io::ErrorKind::InvalidData,
@@ -30,16 +70,11 @@ fn err_invalid_field(field: &str) -> io::Error {
     )
 }
 
-macro_rules! read_field_into {
-    ($reader:ident, $field:literal) => {
-        read_field($reader)?
-            .try_into()
-            .map_err(|_| err_invalid_field($field))
-    };
-}
-
-fn write_field(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
-    let prefix = bytes.len() as u16;
+fn write_field(writer: &mut impl Write, field: &'static str, bytes: &[u8]) -> io::Result<()> {
+    let prefix = u16::try_from(bytes.len()).map_err(|_| FieldTooLong {
+        field,
+        len: bytes.len(),
+    })?;
 
     write_len(writer, prefix)?;
     writer.write_all(bytes)?;
@@ -47,7 +82,7 @@ fn write_field(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
     Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Family {
     Local,
     Wild,
@@ -58,60 +93,428 @@ pub enum Family {
 }
 
 impl Family {
-    fn encode(&self) -> u16 {
+    /// See `family_codes` for the known values
+    pub fn code(&self) -> u16 {
         match self {
-            Self::Local => 256,
-            Self::Wild => 65535, // TODO:
+            Self::Local => FAMILY_LOCAL,
+            Self::Wild => FAMILY_WILD, // TODO:
             Self::Other(x) => *x,
         }
     }
 
-    fn decode(value: u16) -> Self {
+    pub fn from_code(value: u16) -> Self {
         match value {
-            256 => Self::Local,
-            65535 => Self::Wild,
+            FAMILY_LOCAL => Self::Local,
+            FAMILY_WILD => Self::Wild,
             x => Self::Other(x),
         }
     }
 }
 
-#[derive(Debug)]
+// Fields are private so the setters can keep entries encodable
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Entry {
-    pub family: Family,
+    pub(crate) family: Family,
+    pub(crate) address: Vec<u8>,
+    pub(crate) display_number: String,
+    pub(crate) auth_name: Arc<str>,
+    auth_data: AuthData,
+    display_cache: DisplayCache,
+}
+
+/// The secret part of an entry, only reachable through audited accessors.
+/// With `hardened-memory`, kept in a `SecretBuf` like cookies are
+struct AuthData(AuthBytes);
+
+#[cfg(not(feature = "hardened-memory"))]
+type AuthBytes = Vec<u8>;
+#[cfg(feature = "hardened-memory")]
+type AuthBytes = SecretBuf;
+
+impl AuthData {
+    #[cfg(not(feature = "hardened-memory"))]
+    fn new(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    #[cfg(feature = "hardened-memory")]
+    fn new(mut bytes: Vec<u8>) -> Self {
+        let data = Self(SecretBuf::new(&bytes));
+
+        // Don't leave a copy behind on the heap
+        for byte in &mut bytes {
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+
+        data
+    }
+}
+
+impl Deref for AuthData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Clone for AuthData {
+    fn clone(&self) -> Self {
+        Self::new(self.to_vec())
+    }
+}
+
+impl PartialEq for AuthData {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for AuthData {}
+
+impl Hash for AuthData {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
+    }
+}
+
+impl fmt::Debug for AuthData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Redacted(self).fmt(f)
+    }
+}
+
+/// `Entry::display`, parsed along with the display number. Not part of the entry's identity
+#[derive(Debug, Clone, Copy)]
+struct DisplayCache(Option<u32>);
+
+impl DisplayCache {
+    fn parse(display_number: &str) -> Self {
+        let numeric =
+            !display_number.is_empty() && display_number.bytes().all(|b| b.is_ascii_digit());
+
+        Self(numeric.then(|| display_number.parse().ok()).flatten())
+    }
+}
+
+impl PartialEq for DisplayCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for DisplayCache {}
+
+impl Hash for DisplayCache {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+fn check_len(field: &'static str, len: usize) -> std::result::Result<(), FieldTooLong> {
+    match len > u16::MAX as usize {
+        true => Err(FieldTooLong { field, len }),
+        false => Ok(()),
+    }
+}
+
+/// Families with fixed-size addresses. Others, like Local or ServerInterpreted, vary
+const ADDRESS_LENGTHS: &[(u16, usize)] = &[
+    (FAMILY_INTERNET, 4),
+    (FAMILY_DECNET, 2),
+    (FAMILY_INTERNET6, 16),
+];
+
+/// None if addresses of `family` can have any length
+pub(crate) fn expected_address_len(family: u16) -> Option<usize> {
+    ADDRESS_LENGTHS
+        .iter()
+        .find(|(code, _)| *code == family)
+        .map(|(_, len)| *len)
+}
+
+fn check_address(family: Family, address: &[u8]) -> Result<()> {
+    check_len("address", address.len())?;
+
+    match expected_address_len(family.code()) {
+        Some(len) if len != address.len() => Err(Error::InvalidEntry(
+            "address length doesn't match the family",
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Shared by every entry using these, wherever it was parsed
+static WELL_KNOWN_NAMES: LazyLock<[Arc<str>; 2]> =
+    LazyLock::new(|| [MIT_MAGIC_COOKIE_1.into(), XDM_AUTHORIZATION_1.into()]);
+
+/// Protocol name, shared with all other entries if it's a common one
+pub(crate) fn shared_name(name: &str) -> Arc<str> {
+    match WELL_KNOWN_NAMES.iter().find(|known| &***known == name) {
+        Some(known) => known.clone(),
+        None => name.into(),
+    }
+}
+
+/// Deduplicates protocol names across the entries of a parse.
+/// Almost every entry uses the same one, so a linear scan is plenty
+pub(crate) struct Interner(Vec<Arc<str>>);
+
+impl Interner {
+    pub fn new() -> Self {
+        Self(WELL_KNOWN_NAMES.to_vec())
+    }
+
+    pub fn intern(&mut self, name: &str) -> Arc<str> {
+        if let Some(interned) = self.0.iter().find(|interned| &***interned == name) {
+            return interned.clone();
+        }
+
+        let interned: Arc<str> = name.into();
+        self.0.push(interned.clone());
+        interned
+    }
+}
+
+/// An entry as stored, before any validation
+pub(crate) struct RawEntry {
+    pub family: u16,
     pub address: Vec<u8>,
-    pub display_number: String,
-    pub auth_name: String,
+    pub display_number: Vec<u8>,
+    pub auth_name: Vec<u8>,
     pub auth_data: Vec<u8>,
 }
 
-impl Entry {
+impl RawEntry {
     pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
-        let family = Family::decode(match read_len(reader) {
-            Ok(value) => value,
-            Err(e) => {
-                return match e.kind() {
-                    io::ErrorKind::UnexpectedEof => Ok(None),
-                    _ => Err(e),
-                };
-            }
-        });
-
-        Ok(Some(Self {
+        Self::read_family(reader)?
+            .map(|family| Self::read_rest(reader, family))
+            .transpose()
+    }
+
+    /// None at the end of the input
+    pub fn read_family<R: Read>(reader: &mut R) -> io::Result<Option<u16>> {
+        match read_len(reader) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn read_rest<R: Read>(reader: &mut R, family: u16) -> io::Result<Self> {
+        Ok(Self {
             family,
-            address: read_field_into!(reader, "address")?,
-            display_number: read_field_into!(reader, "display_number")?,
-            auth_name: read_field_into!(reader, "auth_name")?,
-            auth_data: read_field_into!(reader, "auth_data")?,
-        }))
+            address: read_field(reader)?,
+            display_number: read_field(reader)?,
+            auth_name: read_field(reader)?,
+            auth_data: read_field(reader)?,
+        })
     }
 
-    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
-        write_len(writer, self.family.encode())?;
-        write_field(writer, &self.address)?;
-        write_field(writer, self.display_number.as_bytes())?;
-        write_field(writer, self.auth_name.as_bytes())?;
-        write_field(writer, &self.auth_data)?;
+    /// Skips the fields after the family, without allocating for them
+    pub fn skip_rest<R: Read>(reader: &mut R) -> io::Result<()> {
+        for _ in 0..4 {
+            skip_field(reader)?;
+        }
+
+        Ok(())
+    }
+
+    /// On failure, returns the name of the offending field
+    pub fn into_entry(self, interner: &mut Interner) -> std::result::Result<Entry, &'static str> {
+        let auth_name = str::from_utf8(&self.auth_name).map_err(|_| "auth_name")?;
+        let display_number =
+            String::from_utf8(self.display_number).map_err(|_| "display_number")?;
+
+        Ok(Entry::from_parts(
+            Family::from_code(self.family),
+            self.address,
+            display_number,
+            interner.intern(auth_name),
+            self.auth_data,
+        ))
+    }
+}
+
+pub(crate) fn read_entry<R: Read>(
+    reader: &mut R,
+    interner: &mut Interner,
+) -> io::Result<Option<Entry>> {
+    match RawEntry::read_from(reader)? {
+        Some(raw) => raw
+            .into_entry(interner)
+            .map(Some)
+            .map_err(err_invalid_field),
+        None => Ok(None),
+    }
+}
+
+impl Entry {
+    /// How every entry is put together, so the display cache can't get out of sync
+    pub(crate) fn from_parts(
+        family: Family,
+        address: Vec<u8>,
+        display_number: String,
+        auth_name: Arc<str>,
+        auth_data: Vec<u8>,
+    ) -> Self {
+        Self {
+            family,
+            address,
+            display_cache: DisplayCache::parse(&display_number),
+            display_number,
+            auth_name,
+            auth_data: AuthData::new(auth_data),
+        }
+    }
+
+    pub fn family(&self) -> Family {
+        self.family
+    }
+
+    pub fn address(&self) -> &[u8] {
+        &self.address
+    }
+
+    /// Empty for entries matching any display
+    pub fn display_number(&self) -> &str {
+        &self.display_number
+    }
+
+    /// The display number as a number, parsed once when it's set.
+    /// None for wildcard entries, and for display numbers that aren't numeric
+    pub fn display(&self) -> Option<u32> {
+        self.display_cache.0
+    }
+
+    /// The auth protocol name, e.g. `MIT-MAGIC-COOKIE-1`
+    pub fn auth_name(&self) -> &str {
+        &self.auth_name
+    }
 
+    /// The secret itself, e.g. the cookie. Notifies the secret access observer
+    pub fn auth_data(&self) -> &[u8] {
+        audit::notify(SecretAccess::RawData, &self.auth_name);
+        &self.auth_data
+    }
+
+    /// `auth_data` for uses that don't hand the secret out of the library
+    pub(crate) fn auth_data_unobserved(&self) -> &[u8] {
+        &self.auth_data
+    }
+
+    #[cfg(feature = "keyring")]
+    /// Like `set_auth_data`, for data that came from somewhere with the same limits
+    pub(crate) fn replace_auth_data(&mut self, auth_data: Vec<u8>) {
+        self.auth_data = AuthData::new(auth_data);
+    }
+
+    /// The protocol name and data pair X11 connection setup takes, e.g. for
+    /// x11rb's `connect_to_stream_with_auth_info`
+    pub fn into_auth_info(self) -> (Vec<u8>, Vec<u8>) {
+        audit::notify(SecretAccess::RawData, &self.auth_name);
+        (self.auth_name.as_bytes().to_vec(), self.auth_data.to_vec())
+    }
+
+    /// Family and address are set together, as the address format depends on the family
+    pub fn set_address(&mut self, family: Family, address: Vec<u8>) -> Result<()> {
+        check_address(family, &address)?;
+        self.family = family;
+        self.address = address;
+        Ok(())
+    }
+
+    pub fn set_display_number(&mut self, display_number: String) -> Result<()> {
+        check_len("display_number", display_number.len())?;
+        self.display_cache = DisplayCache::parse(&display_number);
+        self.display_number = display_number;
+        Ok(())
+    }
+
+    pub fn set_auth_name(&mut self, auth_name: &str) -> Result<()> {
+        check_len("auth_name", auth_name.len())?;
+        self.auth_name = shared_name(auth_name);
         Ok(())
     }
+
+    pub fn set_auth_data(&mut self, auth_data: Vec<u8>) -> Result<()> {
+        check_len("auth_data", auth_data.len())?;
+        self.auth_data = AuthData::new(auth_data);
+        Ok(())
+    }
+
+    /// Checks what the setters check: field lengths, and address lengths for families
+    /// with fixed-size addresses. Parsing doesn't, unless `ParseOptions::validate_addresses` is set
+    pub fn validate(&self) -> Result<()> {
+        check_address(self.family, &self.address)?;
+        Ok(self.check_lengths()?)
+    }
+
+    /// Whether every field fits its length prefix, i.e. the entry can be encoded
+    pub(crate) fn check_lengths(&self) -> std::result::Result<(), FieldTooLong> {
+        check_len("address", self.address.len())?;
+        check_len("display_number", self.display_number.len())?;
+        check_len("auth_name", self.auth_name.len())?;
+        check_len("auth_data", self.auth_data.len())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        read_entry(reader, &mut Interner::new())
+    }
+
+    /// Size of this entry in the on-disk format
+    pub fn encoded_len(&self) -> usize {
+        // family, then a length prefix for each of the 4 fields
+        const PREFIXES_LEN: usize = 2 * 5;
+
+        PREFIXES_LEN
+            + self.address.len()
+            + self.display_number.len()
+            + self.auth_name.len()
+            + self.auth_data.len()
+    }
+
+    /// Fails with `FieldTooLong` before writing anything if a field can't be encoded
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.check_lengths()?;
+        audit::check(SecretAccess::Serialize, &self.auth_name)?;
+        self.encode_to(writer)
+    }
+
+    /// `write_to` without notifying the secret access observer,
+    /// for when the encoding doesn't leave the library, like hashing
+    pub(crate) fn encode_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        encode_parts(
+            writer,
+            self.family,
+            &self.address,
+            &self.display_number,
+            &self.auth_name,
+            &self.auth_data,
+        )
+    }
+
+    /// Like `write_to`, from borrowed fields, for generators that would otherwise
+    /// build an owned entry just to write it out
+    pub fn write_parts_to<W: Write>(
+        writer: &mut W,
+        family: Family,
+        address: &[u8],
+        display_number: &str,
+        auth_name: &str,
+        auth_data: &[u8],
+    ) -> io::Result<()> {
+        check_len("address", address.len())?;
+        check_len("display_number", display_number.len())?;
+        check_len("auth_name", auth_name.len())?;
+        check_len("auth_data", auth_data.len())?;
+        audit::check(SecretAccess::Serialize, auth_name)?;
+
+        encode_parts(
+            writer,
+            family,
+            address,
+            display_number,
+            auth_name,
+            auth_data,
+        )
+    }
 }
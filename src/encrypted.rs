@@ -0,0 +1,83 @@
+use std::{
+    io::{self, Read, Write},
+    ptr,
+};
+
+use crate::{Authority, error::Result, file::AuthorityFile, storage::Storage};
+
+/// An AEAD supplied by the caller, e.g. backed by age, libsodium or a hardware token.
+/// The crate only defines the container around it
+pub trait Sealer {
+    fn seal(&self, plaintext: &[u8], associated_data: &[u8]) -> io::Result<Vec<u8>>;
+    /// Must fail if `ciphertext` or `associated_data` were tampered with
+    fn open(&self, ciphertext: &[u8], associated_data: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+// Authenticated as associated data, so the version can't be swapped
+const HEADER: &[u8; 9] = b"XAUTHENC\x01";
+
+/// Stores an authority encrypted at rest: a fixed header, then the sealed
+/// standard encoding of the entries
+pub struct EncryptedAuthority<C> {
+    sealer: C,
+}
+
+fn zeroize(buf: &mut [u8]) {
+    for byte in buf {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+}
+
+impl<C: Sealer> EncryptedAuthority<C> {
+    pub fn new(sealer: C) -> Self {
+        Self { sealer }
+    }
+
+    pub fn read<R: Read>(&self, reader: &mut R) -> io::Result<Authority> {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+
+        let ciphertext = contents.strip_prefix(HEADER).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "not an encrypted authority")
+        })?;
+
+        let mut plaintext = self.sealer.open(ciphertext, HEADER)?;
+        let authority = Authority::read_from(&mut plaintext.as_slice());
+        zeroize(&mut plaintext);
+
+        authority
+    }
+
+    pub fn write<W: Write>(&self, authority: &Authority, writer: &mut W) -> io::Result<()> {
+        let mut plaintext = Vec::with_capacity(authority.iter().map(|e| e.encoded_len()).sum());
+        let sealed = authority
+            .write_with(&mut plaintext, &Default::default())
+            .and_then(|_| self.sealer.seal(&plaintext, HEADER));
+        zeroize(&mut plaintext);
+
+        writer.write_all(HEADER)?;
+        writer.write_all(&sealed?)
+    }
+}
+
+impl<S: Storage> AuthorityFile<S> {
+    pub fn get_encrypted<C: Sealer>(
+        &mut self,
+        container: &EncryptedAuthority<C>,
+    ) -> Result<Authority> {
+        self.start_read()?;
+        Ok(container.read(self.file_mut())?)
+    }
+
+    pub fn set_encrypted<C: Sealer>(
+        &mut self,
+        container: &EncryptedAuthority<C>,
+        authority: &Authority,
+    ) -> Result<()> {
+        // Seal first, so a failing sealer leaves the file alone
+        let mut sealed = Vec::new();
+        container.write(authority, &mut sealed)?;
+
+        self.set_raw(&sealed)
+    }
+}
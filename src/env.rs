@@ -0,0 +1,92 @@
+use std::{env, io, path::PathBuf, process::Command};
+
+use crate::{display::DisplayName, error::Result};
+
+pub(crate) fn env_path(name: &str) -> io::Result<PathBuf> {
+    env::var_os(name)
+        .map(PathBuf::from)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("${name} is not set")))
+}
+
+pub(crate) fn display_from_env() -> Result<DisplayName> {
+    let display = env::var("DISPLAY")
+        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "$DISPLAY is not set"))?;
+
+    display.parse()
+}
+
+/// `DISPLAY` and `XAUTHORITY` of a session, together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayEnv {
+    pub display: DisplayName,
+    pub xauthority: PathBuf,
+}
+
+impl DisplayEnv {
+    pub fn new(display: DisplayName, xauthority: PathBuf) -> Self {
+        Self {
+            display,
+            xauthority,
+        }
+    }
+
+    /// From the current environment. Without `$XAUTHORITY`, the authority is
+    /// `~/.Xauthority`, as libXau has it
+    pub fn capture() -> Result<Self> {
+        let xauthority = match env_path("XAUTHORITY") {
+            Ok(path) if !path.as_os_str().is_empty() => path,
+            _ => env_path("HOME")?.join(".Xauthority"),
+        };
+
+        Ok(Self::new(display_from_env()?, xauthority))
+    }
+
+    /// Fails for relative authority paths, which would resolve differently in
+    /// processes with another working directory, and for ones that aren't UTF-8,
+    /// as `to_env_pairs` couldn't pass them on unchanged
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| -> Result<()> {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("XAUTHORITY {}: {reason}", self.xauthority.display()),
+            )
+            .into())
+        };
+
+        if !self.xauthority.is_absolute() {
+            return invalid("not an absolute path");
+        }
+
+        if self.xauthority.to_str().is_none() {
+            return invalid("not valid UTF-8");
+        }
+
+        Ok(())
+    }
+
+    pub fn to_env_pairs(&self) -> [(&'static str, String); 2] {
+        [
+            ("XAUTHORITY", self.xauthority.display().to_string()),
+            ("DISPLAY", self.display.to_string()),
+        ]
+    }
+
+    /// Sets both for a process about to be launched
+    pub fn apply_to(&self, command: &mut Command) {
+        command
+            .env("XAUTHORITY", &self.xauthority)
+            .env("DISPLAY", self.display.to_string());
+    }
+
+    /// Sets both in the current process's environment
+    ///
+    /// # Safety
+    /// No other thread may be reading or writing the environment meanwhile,
+    /// see `std::env::set_var`
+    pub unsafe fn apply(&self) {
+        unsafe {
+            env::set_var("XAUTHORITY", &self.xauthority);
+            env::set_var("DISPLAY", self.display.to_string());
+        }
+    }
+}
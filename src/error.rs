@@ -0,0 +1,184 @@
+use std::{fmt, io, path::PathBuf, time::Duration};
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// `create` found a file at this path. Use `open`, or `open_or_create` to do both
+    AlreadyExists(PathBuf),
+    /// Usually a misconfigured `$XAUTHORITY`
+    IsADirectory(PathBuf),
+    /// The path is a fifo, socket, device or similar
+    NotRegularFile(PathBuf),
+    /// Another process holds the lock. Retry later
+    LockBusy(PathBuf),
+    /// A lock is held, but looks abandoned. Consider `Lock::break_stale`.
+    /// `age` is how long the lock went untouched, when known
+    LockStale {
+        path: PathBuf,
+        age: Option<Duration>,
+    },
+    /// Our lock was broken: the lock files were removed or replaced,
+    /// most likely by a process that considered them stale
+    LockLost,
+    InvalidDisplayName(String),
+    /// An entry that can't be stored, see the reason
+    InvalidEntry(&'static str),
+    FieldTooLong(FieldTooLong),
+    /// The file isn't owned by the expected user, or others can write to it.
+    /// See `AuthorityFile::verify_ownership`
+    UntrustedFile {
+        path: Option<PathBuf>,
+        owner: u32,
+        mode: u32,
+    },
+    /// The file is over the limit set with `AuthorityFile::max_size`
+    FileTooLarge {
+        size: u64,
+        limit: u64,
+    },
+    /// A line of an xauth script passed to `Authority::apply_script` failed
+    Script {
+        line: usize,
+        message: String,
+    },
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// What went wrong, without the details. Stays stable as variants gain fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    AlreadyExists,
+    IsADirectory,
+    NotRegularFile,
+    LockBusy,
+    LockStale,
+    LockLost,
+    InvalidDisplayName,
+    InvalidEntry,
+    FieldTooLong,
+    UntrustedFile,
+    FileTooLarge,
+    Script,
+    /// Any other io error, see `source` for the underlying one
+    Io,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::AlreadyExists(_) => ErrorKind::AlreadyExists,
+            Self::IsADirectory(_) => ErrorKind::IsADirectory,
+            Self::NotRegularFile(_) => ErrorKind::NotRegularFile,
+            Self::LockBusy(_) => ErrorKind::LockBusy,
+            Self::LockStale { .. } => ErrorKind::LockStale,
+            Self::LockLost => ErrorKind::LockLost,
+            Self::InvalidDisplayName(_) => ErrorKind::InvalidDisplayName,
+            Self::InvalidEntry(_) => ErrorKind::InvalidEntry,
+            Self::FieldTooLong(_) => ErrorKind::FieldTooLong,
+            Self::UntrustedFile { .. } => ErrorKind::UntrustedFile,
+            Self::FileTooLarge { .. } => ErrorKind::FileTooLarge,
+            Self::Script { .. } => ErrorKind::Script,
+            Self::Io(_) => ErrorKind::Io,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlreadyExists(path) => {
+                write!(f, "authority file already exists: {}", path.display())
+            }
+            Self::IsADirectory(path) => {
+                write!(f, "authority path is a directory: {}", path.display())
+            }
+            Self::NotRegularFile(path) => {
+                write!(
+                    f,
+                    "authority path is not a regular file: {}",
+                    path.display()
+                )
+            }
+            Self::LockBusy(path) => {
+                write!(f, "authority file is locked: {}", path.display())
+            }
+            Self::LockStale { path, .. } => {
+                write!(f, "authority file has a stale lock: {}", path.display())
+            }
+            Self::LockLost => f.write_str("lock on the authority file was lost"),
+            Self::InvalidDisplayName(name) => write!(f, "invalid display name: {name}"),
+            Self::InvalidEntry(reason) => write!(f, "invalid entry: {reason}"),
+            Self::FieldTooLong(e) => e.fmt(f),
+            Self::UntrustedFile { path, owner, mode } => {
+                f.write_str("untrusted authority file")?;
+                if let Some(path) = path {
+                    write!(f, " {}", path.display())?;
+                }
+                write!(f, ": owned by uid {owner}, mode {mode:o}")
+            }
+            Self::FileTooLarge { size, limit } => {
+                write!(f, "authority file is {size} bytes, the limit is {limit}")
+            }
+            Self::Script { line, message } => write!(f, "script line {line}: {message}"),
+            Self::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        // Serialization reports these through io::Error, unwrap them again
+        match value
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<FieldTooLong>())
+        {
+            Some(e) => Self::FieldTooLong(e.clone()),
+            None => Self::Io(value),
+        }
+    }
+}
+
+/// A field doesn't fit the format's 16 bit length prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldTooLong {
+    pub field: &'static str,
+    pub len: usize,
+}
+
+impl fmt::Display for FieldTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is {} bytes, the maximum is {}",
+            self.field,
+            self.len,
+            u16::MAX
+        )
+    }
+}
+
+impl std::error::Error for FieldTooLong {}
+
+impl From<FieldTooLong> for Error {
+    fn from(value: FieldTooLong) -> Self {
+        Self::FieldTooLong(value)
+    }
+}
+
+impl From<FieldTooLong> for io::Error {
+    fn from(value: FieldTooLong) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, value)
+    }
+}
@@ -0,0 +1,13 @@
+//! Address family codes, as defined by X.h and Xauth.h
+
+pub const FAMILY_INTERNET: u16 = 0;
+pub const FAMILY_DECNET: u16 = 1;
+pub const FAMILY_CHAOS: u16 = 2;
+pub const FAMILY_SERVER_INTERPRETED: u16 = 5;
+pub const FAMILY_INTERNET6: u16 = 6;
+
+pub const FAMILY_LOCAL_HOST: u16 = 252;
+pub const FAMILY_KRB5_PRINCIPAL: u16 = 253;
+pub const FAMILY_NETNAME: u16 = 254;
+pub const FAMILY_LOCAL: u16 = 256;
+pub const FAMILY_WILD: u16 = 65535;
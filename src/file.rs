@@ -0,0 +1,1232 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, Read, Seek},
+    os::{
+        fd::{FromRawFd, OwnedFd, RawFd},
+        unix::fs::MetadataExt,
+    },
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    Authority, Entry,
+    compat::CompatOptions,
+    error::{Error, Result},
+    lock::{DotLock, Lock, LockGuard, StaleLock},
+    metrics::{self, Metric},
+    parse::{ParseOptions, ParseWarning, Parsed},
+    report::AuditOutput,
+    retry::RetryPolicy,
+    storage::{Detached, Fs, MemoryFile, MemoryStorage, Storage, StorageFile},
+    timestamps::Timestamps,
+};
+
+unsafe extern "C" {
+    fn geteuid() -> u32;
+}
+
+/// Permissions of newly created authority files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileMode {
+    /// Exactly 0600, whatever the umask
+    #[default]
+    Private,
+    /// 0666 filtered through the process umask, like most programs create files
+    Umask,
+    /// Exactly this mode, whatever the umask.
+    /// For setups that intentionally share the file, e.g. 0640 for a kiosk group
+    Exact(u32),
+}
+
+/// What `append_with` does with entries whose key is already in the file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Duplicates {
+    /// Append anyway. Older entries will shadow the new ones
+    #[default]
+    Keep,
+    /// Leave the existing entry, drop the new one
+    Skip,
+    /// Remove the existing entry, rewriting the file
+    Replace,
+}
+
+/// What a recorded `AuthorityFile` operation changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeSet {
+    pub added: Vec<Entry>,
+    /// Entries removed or replaced
+    pub removed: Vec<Entry>,
+}
+
+impl ChangeSet {
+    fn added(added: Vec<Entry>) -> Self {
+        Self {
+            added,
+            removed: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Options for creating authority files, in the style of `std::fs::OpenOptions`
+#[derive(Debug, Clone, Default)]
+pub struct CreateOptions {
+    parents_mode: Option<u32>,
+    mode: FileMode,
+}
+
+impl CreateOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create missing parent directories with `mode`, e.g. 0o700
+    pub fn parents(mut self, mode: u32) -> Self {
+        self.parents_mode = Some(mode);
+        self
+    }
+
+    pub fn mode(mut self, mode: FileMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn create(&self, path: &Path) -> Result<AuthorityFile> {
+        self.create_in(&Fs::default(), path)
+    }
+
+    pub fn open_or_create(&self, path: &Path) -> Result<AuthorityFile> {
+        self.open_or_create_in(&Fs::default(), path)
+    }
+
+    pub fn create_in<S: Storage>(&self, storage: &S, path: &Path) -> Result<AuthorityFile<S>> {
+        let file = self.create_file(storage, path)?;
+        let lock = lock_in(storage, path)?;
+
+        Ok(AuthorityFile::new(file, Some(lock), Some(path)))
+    }
+
+    pub fn open_or_create_in<S: Storage>(
+        &self,
+        storage: &S,
+        path: &Path,
+    ) -> Result<AuthorityFile<S>> {
+        self.create_or_existing_in(storage, path)
+            .map(Created::into_inner)
+    }
+
+    /// Like `create`, but opens and locks the file if it already exists.
+    /// The result tells which happened
+    pub fn create_or_existing(&self, path: &Path) -> Result<Created> {
+        self.create_or_existing_in(&Fs::default(), path)
+    }
+
+    pub fn create_or_existing_in<S: Storage>(
+        &self,
+        storage: &S,
+        path: &Path,
+    ) -> Result<Created<S>> {
+        loop {
+            match self.create_in(storage, path) {
+                Ok(file) => return Ok(Created::New(file)),
+                Err(Error::AlreadyExists(_)) => {}
+                Err(e) => return Err(e),
+            }
+
+            match AuthorityFile::open_in(storage, path) {
+                Ok(file) => return Ok(Created::Existing(file)),
+                // Removed between our create and open, try creating again
+                Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub(crate) fn create_file<S: Storage>(&self, storage: &S, path: &Path) -> Result<S::File> {
+        if let Some(mode) = self.parents_mode
+            && let Some(parent) = path.parent()
+        {
+            storage.create_dir_all(parent, mode)?;
+        }
+
+        let (create_mode, exact_mode) = match self.mode {
+            FileMode::Private => (0o600, Some(0o600)),
+            FileMode::Umask => (0o666, None),
+            FileMode::Exact(mode) => (mode, Some(mode)),
+        };
+
+        let mut file = storage
+            .create(path, create_mode)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::AlreadyExists => Error::AlreadyExists(path.to_path_buf()),
+                _ => Error::from(e),
+            })?;
+
+        if let Some(mode) = exact_mode {
+            file.set_mode(mode)?;
+        }
+
+        Ok(file)
+    }
+}
+
+pub(crate) fn lock_in<S: Storage>(storage: &S, path: &Path) -> Result<S::Lock> {
+    lock_with_retry_in(storage, path, RetryPolicy::none())
+}
+
+/// `lock_in`, trying again while the lock is busy
+fn lock_with_retry_in<S: Storage>(storage: &S, path: &Path, retry: RetryPolicy) -> Result<S::Lock> {
+    let start = Instant::now();
+    let lock = retry.run(
+        || storage.lock(path),
+        |e| e.kind() == io::ErrorKind::AlreadyExists,
+    );
+    metrics::record(Metric::LockWait {
+        wait: start.elapsed(),
+        acquired: lock.is_ok(),
+    });
+
+    lock.map_err(|e| {
+        if e.kind() != io::ErrorKind::AlreadyExists {
+            return e.into();
+        }
+
+        let path = path.to_path_buf();
+        match StaleLock::from_error(&e) {
+            Some(stale) => Error::LockStale {
+                path,
+                age: stale.age,
+            },
+            None => Error::LockBusy(path),
+        }
+    })
+}
+
+/// How `AuthorityFile::from_raw_fd` keeps other processes away from the file
+pub enum LockDisposition {
+    /// We already hold the lock
+    Held(Lock),
+    /// Take the lock for the file at this path.
+    /// The path is only used for locking, the file is never reopened through it
+    Acquire(PathBuf),
+    /// Nothing else uses the file, see `AuthorityFile::from_existing_unlocked`
+    Unlocked,
+}
+
+/// How `AuthorityFile::open_with_mode` treats the file's lock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OpenMode {
+    #[default]
+    Locked,
+    /// Like `xauth -i`: don't take the lock, for recovery tools reading files whose lock is wedged.
+    /// Nothing keeps other writers away, so with `read_only` every write fails instead.
+    /// Reported as `Metric::LockIgnored`
+    IgnoreLock { read_only: bool },
+}
+
+/// Result of `CreateOptions::create_or_existing`
+pub enum Created<S: Storage = Fs> {
+    New(AuthorityFile<S>),
+    /// The file already existed. It was opened and locked as-is,
+    /// `CreateOptions` weren't applied to it
+    Existing(AuthorityFile<S>),
+}
+
+impl<S: Storage> Created<S> {
+    pub fn is_new(&self) -> bool {
+        matches!(self, Self::New(_))
+    }
+
+    pub fn into_inner(self) -> AuthorityFile<S> {
+        match self {
+            Self::New(file) | Self::Existing(file) => file,
+        }
+    }
+}
+
+pub struct AuthorityFile<S: Storage = Fs> {
+    file: S::File,
+    lock: Option<S::Lock>,
+    // Unknown when constructed from an already open file
+    path: Option<PathBuf>,
+    verify_lock: bool,
+    // Only ever enabled for `Fs`, the sidecar is accessed through std::fs
+    track_times: bool,
+    compat: CompatOptions,
+    dry_run: bool,
+    max_size: Option<u64>,
+    read_only: bool,
+    // Whether `path` may be used to replace a stale handle. Not for files the caller opened
+    // and checked, whatever now is at the path never was
+    reopenable: bool,
+}
+
+/// See `AuthorityFile::snapshot`
+pub struct Snapshot {
+    pub bytes: Vec<u8>,
+    pub authority: Authority,
+    pub warnings: Vec<ParseWarning>,
+}
+
+/// See `AuthorityFile::expiry`
+pub struct Expiry {
+    timestamps: Option<Timestamps>,
+    modified: Option<SystemTime>,
+    // None if `max_age` reaches before the epoch, nothing is that old
+    cutoff: Option<SystemTime>,
+}
+
+impl Expiry {
+    pub fn is_expired(&self, entry: &Entry) -> bool {
+        let Some(cutoff) = self.cutoff else {
+            return false;
+        };
+
+        let created = self
+            .timestamps
+            .as_ref()
+            .and_then(|timestamps| timestamps.created(entry))
+            .or(self.modified);
+
+        created.is_some_and(|created| created < cutoff)
+    }
+}
+
+/// What `xauth info` shows
+#[derive(Debug, Clone)]
+pub struct AuthorityInfo {
+    pub path: Option<PathBuf>,
+    /// We hold a lock on the file, and nobody broke it
+    pub locked: bool,
+    pub entries: usize,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl AuthorityFile {
+    pub fn from_existing(file: File, lock: Lock) -> Result<Self> {
+        Ok(Self::new(file, Some(lock), None))
+    }
+
+    /// # Safety
+    /// the caller should ensure no other process will open the same file
+    /// Note that for files created by other programs, this is generraly impossible to guarantee
+    /// Thus, this api is not recommended, unless you are absolutely sure what you're doing
+    pub unsafe fn from_existing_unlocked(file: File) -> Self {
+        Self::new(file, None, None)
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::open_in(&Fs::default(), path)
+    }
+
+    pub fn open_with_mode(path: &Path, mode: OpenMode) -> Result<Self> {
+        Self::open_with_mode_in(&Fs::default(), path, mode)
+    }
+
+    /// Like `open`, retrying according to `retry` while the lock is busy.
+    /// Shorthand for a `DotLock::retry` storage
+    pub fn open_with_retry(path: &Path, retry: RetryPolicy) -> Result<Self> {
+        Self::open_in(
+            &Fs::with_lock_strategy(DotLock::default().retry(retry)),
+            path,
+        )
+    }
+
+    /// Like `open`, followed by `verify_ownership`, for paths from an untrusted `$XAUTHORITY`
+    pub fn open_trusted(path: &Path, uid: u32) -> Result<Self> {
+        let mut file = Self::open(path)?;
+        file.verify_ownership(uid)?;
+        // A replacement wouldn't have been verified
+        file.reopenable = false;
+        Ok(file)
+    }
+
+    /// Fails with `UntrustedFile` unless the file is owned by `uid` and neither group nor
+    /// world writable. For setuid helpers and root daemons about to trust a user supplied file.
+    ///
+    /// `uid` is the user the file should belong to, e.g. the real uid of a setuid helper's
+    /// caller. Not the effective uid: for setuid and root callers, that's root
+    pub fn verify_ownership(&self, uid: u32) -> Result<()> {
+        let metadata = self.file.metadata()?;
+
+        if metadata.uid() != uid || metadata.mode() & 0o022 != 0 {
+            return Err(Error::UntrustedFile {
+                path: self.path.clone(),
+                owner: metadata.uid(),
+                mode: metadata.mode() & 0o7777,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// What `verify_ownership` checks, along with the entries, as a report instead of an error
+    pub fn audit(&mut self) -> Result<AuditOutput> {
+        let metadata = self.file.metadata()?;
+        let (authority, warnings) = self.get_lenient()?;
+
+        let mut seen = HashSet::new();
+        let shadowed_entries = authority
+            .iter()
+            .filter(|entry| !seen.insert(entry.key()))
+            .count();
+
+        Ok(AuditOutput {
+            path: self.path.as_ref().map(|path| path.display().to_string()),
+            owner: metadata.uid(),
+            mode: metadata.mode() & 0o7777,
+            owned_by_us: metadata.uid() == unsafe { geteuid() },
+            writable_by_others: metadata.mode() & 0o022 != 0,
+            invalid_entries: authority
+                .iter()
+                .filter(|entry| entry.validate().is_err())
+                .count(),
+            shadowed_entries,
+            parse_warnings: warnings.iter().map(ToString::to_string).collect(),
+        })
+    }
+
+    pub fn create(path: &Path) -> Result<Self> {
+        Self::create_in(&Fs::default(), path)
+    }
+
+    pub fn open_or_create(path: &Path) -> Result<Self> {
+        Self::open_or_create_in(&Fs::default(), path)
+    }
+
+    /// # Safety
+    /// the caller should ensure no other process will open the same path
+    // TODO: add examples on how to guarantee that
+    pub unsafe fn create_unlocked(path: &Path) -> Result<Self> {
+        let file = CreateOptions::new().create_file(&Fs::<DotLock>::default(), path)?;
+        Ok(Self::new(file, None, Some(path)))
+    }
+
+    /// Takes over an already open authority file, e.g. one passed over a unix socket
+    /// by a broker that resolved and verified the path.
+    ///
+    /// # Safety
+    /// `fd` must be an open, readable and writable regular file that nothing else owns.
+    /// With `LockDisposition::Unlocked`, the safety requirements of
+    /// `from_existing_unlocked` apply as well
+    pub unsafe fn from_raw_fd(fd: RawFd, lock: LockDisposition) -> Result<Self> {
+        let file = unsafe { File::from_raw_fd(fd) };
+        Self::from_file(file, lock)
+    }
+
+    /// Like `from_raw_fd`, for descriptors handed over by systemd's fd store or a supervisor.
+    /// `fd` must be opened for reading and writing.
+    ///
+    /// `LockDisposition::Unlocked` is rejected, as skipping the lock needs `from_raw_fd`
+    pub fn from_fd(fd: OwnedFd, lock: LockDisposition) -> Result<Self> {
+        if let LockDisposition::Unlocked = lock {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unlocked descriptors need the unsafe from_raw_fd",
+            )
+            .into());
+        }
+
+        Self::from_file(File::from(fd), lock)
+    }
+
+    fn from_file(file: File, lock: LockDisposition) -> Result<Self> {
+        let (lock, path) = match lock {
+            LockDisposition::Held(lock) => (Some(lock), None),
+            LockDisposition::Acquire(path) => {
+                (Some(lock_in(&Fs::<DotLock>::default(), &path)?), Some(path))
+            }
+            LockDisposition::Unlocked => (None, None),
+        };
+
+        let mut file = Self::new(file, lock, path.as_deref());
+        file.reopenable = false;
+        Ok(file)
+    }
+
+    /// Splits into the descriptor, for handing it to an fd store, and the lock.
+    /// Dropping the lock lets others in, even while the descriptor is kept open
+    pub fn into_fd(self) -> (OwnedFd, Option<Lock>) {
+        (self.file.into(), self.lock)
+    }
+
+    pub(crate) fn as_file(&self) -> &File {
+        &self.file
+    }
+
+    /// Record when entries were added in a `<file>-t` sidecar, for `prune_older_than`.
+    /// Has no effect on files opened without a path
+    pub fn record_creation_times(&mut self, enabled: bool) {
+        self.track_times = enabled;
+    }
+
+    /// Which entries were added more than `max_age` ago, judged like `prune_older_than`.
+    /// For `Authority::get_best_unexpired`
+    pub fn expiry(&self, max_age: Duration) -> Result<Expiry> {
+        let timestamps = match &self.path {
+            Some(path) => Some(Timestamps::load(path)?),
+            None => None,
+        };
+
+        Ok(Expiry {
+            timestamps,
+            modified: self.file.modified()?,
+            cutoff: SystemTime::now().checked_sub(max_age),
+        })
+    }
+
+    /// Removes entries added more than `max_age` ago. Returns how many were removed.
+    ///
+    /// Entries without a recorded creation time are judged by the file's modification time.
+    /// No entry can be newer than that, so they are never removed early.
+    /// Without either, entries are kept
+    pub fn prune_older_than(&mut self, max_age: Duration) -> Result<usize> {
+        Ok(self.prune_older_than_recorded(max_age)?.removed.len())
+    }
+
+    /// Like `prune_older_than`, returning what changed, for `apply_revert`
+    pub fn prune_older_than_recorded(&mut self, max_age: Duration) -> Result<ChangeSet> {
+        let expiry = self.expiry(max_age)?;
+        let (removed, kept): (Vec<Entry>, Vec<Entry>) = self
+            .get()?
+            .into_iter()
+            .partition(|entry| expiry.is_expired(entry));
+
+        if !removed.is_empty() {
+            self.set(Authority::new(Some(kept)))?;
+            if !self.dry_run {
+                metrics::record(Metric::Pruned(removed.len()));
+            }
+        }
+
+        Ok(ChangeSet {
+            added: Vec::new(),
+            removed,
+        })
+    }
+}
+
+impl AuthorityFile<MemoryStorage> {
+    /// An empty authority file that never touches the filesystem.
+    /// Locking is a no-op, as nothing else can reach the file
+    pub fn in_memory() -> Self {
+        Self::from_bytes(Vec::new())
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self::new(MemoryFile::detached(bytes), None, None)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.file.contents()
+    }
+}
+
+impl<F: StorageFile> AuthorityFile<Detached<F>> {
+    /// Runs over any file-like `file`, e.g. a `Cursor<Vec<u8>>` or a `Stream`.
+    /// There's no locking, as nothing else can reach the file through us
+    pub fn from_stream(file: F) -> Self {
+        Self::new(file, None, None)
+    }
+
+    pub fn into_inner(self) -> F {
+        self.file
+    }
+}
+
+impl<S: Storage> AuthorityFile<S> {
+    fn new(file: S::File, lock: Option<S::Lock>, path: Option<&Path>) -> Self {
+        Self {
+            file,
+            lock,
+            path: path.map(Path::to_path_buf),
+            verify_lock: false,
+            track_times: false,
+            compat: CompatOptions::default(),
+            dry_run: false,
+            max_size: None,
+            read_only: false,
+            reopenable: true,
+        }
+    }
+
+    pub fn open_in(storage: &S, path: &Path) -> Result<Self> {
+        let lock = lock_in(storage, path)?;
+        let file = Self::open_file(storage, path, false)?;
+
+        Ok(Self::new(file, Some(lock), Some(path)))
+    }
+
+    /// Like `open_in`, retrying according to `retry` while the lock is busy.
+    /// On top of any retries the storage's lock strategy does itself
+    pub fn open_with_retry_in(storage: &S, path: &Path, retry: RetryPolicy) -> Result<Self> {
+        let lock = lock_with_retry_in(storage, path, retry)?;
+        let file = Self::open_file(storage, path, false)?;
+
+        Ok(Self::new(file, Some(lock), Some(path)))
+    }
+
+    pub fn open_with_mode_in(storage: &S, path: &Path, mode: OpenMode) -> Result<Self> {
+        let OpenMode::IgnoreLock { read_only } = mode else {
+            return Self::open_in(storage, path);
+        };
+
+        metrics::record(Metric::LockIgnored);
+        let mut file = Self::new(Self::open_file(storage, path, read_only)?, None, Some(path));
+        file.read_only = read_only;
+
+        Ok(file)
+    }
+
+    /// Opens `path` under a lock taken beforehand, with `create` creating it privately if it's missing
+    pub(crate) fn open_locked_in(
+        storage: &S,
+        path: &Path,
+        lock: S::Lock,
+        create: bool,
+    ) -> Result<Self> {
+        let file = match create {
+            true => match CreateOptions::new().create_file(storage, path) {
+                Ok(file) => file,
+                Err(Error::AlreadyExists(_)) => Self::open_file(storage, path, false)?,
+                Err(e) => return Err(e),
+            },
+            false => Self::open_file(storage, path, false)?,
+        };
+
+        Ok(Self::new(file, Some(lock), Some(path)))
+    }
+
+    fn open_file(storage: &S, path: &Path, read_only: bool) -> Result<S::File> {
+        let file = match read_only {
+            true => storage.open_read_only(path),
+            false => storage.open(path),
+        };
+
+        let file = file.map_err(|e| match e.kind() {
+            io::ErrorKind::IsADirectory => Error::IsADirectory(path.to_path_buf()),
+            _ => e.into(),
+        })?;
+
+        if !file.is_regular()? {
+            return Err(Error::NotRegularFile(path.to_path_buf()));
+        }
+
+        Ok(file)
+    }
+
+    pub fn create_in(storage: &S, path: &Path) -> Result<Self> {
+        CreateOptions::new().create_in(storage, path)
+    }
+
+    pub fn open_or_create_in(storage: &S, path: &Path) -> Result<Self> {
+        CreateOptions::new().open_or_create_in(storage, path)
+    }
+
+    /// Encoding deviations applied to everything written from now on
+    pub fn set_compat(&mut self, compat: CompatOptions) {
+        self.compat = compat;
+    }
+
+    /// Refuse to read files larger than `max` bytes, e.g. a decoy behind a user supplied
+    /// `$XAUTHORITY`. Files too short to hold a single entry aren't parsed either,
+    /// `snapshot` still takes them
+    pub fn max_size(&mut self, max: Option<u64>) {
+        self.max_size = max;
+    }
+
+    /// Checks the size against `max_size`, then rewinds for reading from the start
+    pub(crate) fn start_read(&mut self) -> Result<()> {
+        if let Some(limit) = self.max_size {
+            let size = self.file.seek(io::SeekFrom::End(0))?;
+
+            if size > limit {
+                return Err(Error::FileTooLarge { size, limit });
+            }
+        }
+
+        Ok(self.file.rewind()?)
+    }
+
+    /// Validate writes, but don't perform them. The `_recorded` methods
+    /// still return what would have changed
+    pub fn dry_run(&mut self, enabled: bool) {
+        self.dry_run = enabled;
+    }
+
+    /// Whether a write should go ahead: `authority` is valid, and this isn't a dry run
+    fn should_write(&self, authority: &Authority) -> Result<bool> {
+        self.check_lock()?;
+
+        if self.dry_run {
+            for entry in authority.iter() {
+                entry.check_lengths()?;
+            }
+        }
+
+        Ok(!self.dry_run)
+    }
+
+    /// Before every write, check that our lock wasn't broken by another process.
+    /// Costs a couple of stat calls per write
+    pub fn verify_lock_before_write(&mut self, enabled: bool) {
+        self.verify_lock = enabled;
+    }
+
+    fn check_lock(&self) -> Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "opened read-only, ignoring the lock",
+            )
+            .into());
+        }
+
+        if self.verify_lock
+            && let Some(lock) = &self.lock
+            && !lock.still_held()?
+        {
+            return Err(Error::LockLost);
+        }
+
+        Ok(())
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn info(&mut self) -> Result<AuthorityInfo> {
+        let locked = match &self.lock {
+            Some(lock) => lock.still_held()?,
+            None => false,
+        };
+
+        let entries = self.get()?.len();
+        let size = self.file.seek(io::SeekFrom::End(0))?;
+
+        Ok(AuthorityInfo {
+            path: self.path.clone(),
+            locked,
+            entries,
+            size,
+            modified: self.file.modified()?,
+        })
+    }
+
+    /// The file's exact contents along with their parsed form, both read in one go under our lock.
+    /// Parsing is lenient, so damaged files can still be archived
+    pub fn snapshot(&mut self) -> Result<Snapshot> {
+        let bytes = self.read_file(|file| {
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes)?;
+            Ok(bytes)
+        })?;
+        let (mut authority, warnings) =
+            metrics::read(Authority::read_lenient(&mut bytes.as_slice()))?;
+        self.compat.restore(&mut authority.0);
+
+        Ok(Snapshot {
+            bytes,
+            authority,
+            warnings,
+        })
+    }
+
+    pub fn get(&mut self) -> Result<Authority> {
+        let mut authority = self.parse_file(Authority::read_from)?;
+        self.compat.restore(&mut authority.0);
+
+        metrics::record(Metric::Read);
+        Ok(authority)
+    }
+
+    /// Like `get`, but skips undecodable entries, reporting them as warnings
+    pub fn get_lenient(&mut self) -> Result<(Authority, Vec<ParseWarning>)> {
+        let (mut authority, warnings) = metrics::read(self.parse_file(Authority::read_lenient))?;
+        self.compat.restore(&mut authority.0);
+
+        Ok((authority, warnings))
+    }
+
+    pub fn get_with(&mut self, options: &ParseOptions) -> Result<(Authority, Vec<ParseWarning>)> {
+        let (mut authority, warnings) = metrics::read(self.parse_file(|file| options.read(file)))?;
+        self.compat.restore(&mut authority.0);
+
+        Ok((authority, warnings))
+    }
+
+    /// Like `get_with`, along with the byte range of each entry in the file
+    pub fn get_with_offsets(&mut self, options: &ParseOptions) -> Result<Parsed> {
+        let (mut entries, warnings) =
+            metrics::read(self.parse_file(|file| options.read_with_offsets(file)))?;
+        self.compat
+            .restore(entries.iter_mut().map(|(_, entry)| entry));
+
+        Ok((entries, warnings))
+    }
+
+    /// `read_file` for parsing the contents, which with a `max_size` need to fit an entry
+    fn parse_file<T>(&mut self, parse: impl FnMut(&mut S::File) -> io::Result<T>) -> Result<T> {
+        if self.max_size.is_some() {
+            let size = self.file.seek(io::SeekFrom::End(0))?;
+
+            // Family and four length prefixes
+            if size > 0 && size < 10 {
+                return Err(Error::InvalidEntry("file is too short to hold an entry"));
+            }
+        }
+
+        self.read_file(parse)
+    }
+
+    /// Runs `read` from the start of the file. If another NFS client replaced the file
+    /// since we opened it, reopens it by path and tries again. Files taken over
+    /// from a descriptor, or verified with `open_trusted`, fail with the ESTALE error instead
+    fn read_file<T>(&mut self, mut read: impl FnMut(&mut S::File) -> io::Result<T>) -> Result<T> {
+        self.start_read()?;
+
+        match read(&mut self.file) {
+            Err(e) if RetryPolicy::is_stale_handle(&e) && self.reopen()? => {
+                self.start_read()?;
+                Ok(read(&mut self.file)?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Returns false if the file can't be reopened, e.g. without a path
+    fn reopen(&mut self) -> Result<bool> {
+        let Some(file) = (match &self.path {
+            Some(path) if self.reopenable => self.file.reopen(path)?,
+            _ => None,
+        }) else {
+            return Ok(false);
+        };
+
+        // Whoever rewrote the file should have held the lock, leaving ours broken
+        if let Some(lock) = &self.lock
+            && !lock.still_held()?
+        {
+            return Err(Error::LockLost);
+        }
+
+        self.file = file;
+        Ok(true)
+    }
+
+    pub fn set(&mut self, authority: Authority) -> Result<()> {
+        self.set_ref(&authority)
+    }
+
+    pub(crate) fn set_ref(&mut self, authority: &Authority) -> Result<()> {
+        if !self.should_write(authority)? {
+            return Ok(());
+        }
+
+        let len: usize = authority
+            .iter()
+            .map(|entry| self.compat.apply(entry).encoded_len())
+            .sum();
+        self.check_fits(len as u64)?;
+
+        self.file.rewind()?;
+        authority.write_with(&mut self.file, &self.compat)?;
+
+        // Drop leftovers in case the previous contents were longer
+        let len = self.file.stream_position()?;
+        self.file.set_len(len)?;
+        metrics::record(Metric::Write);
+
+        self.update_times(|timestamps| {
+            timestamps.retain(authority.iter());
+            timestamps.record(authority.iter());
+        })
+    }
+
+    /// Fail before writing anything, rather than leave a mix of old and new contents
+    fn check_fits(&mut self, len: u64) -> Result<()> {
+        if !self.file.can_truncate() && len < self.file.seek(io::SeekFrom::End(0))? {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "can't shrink a file that doesn't support truncation",
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the contents with `bytes`, for encodings other than the standard one
+    #[cfg(feature = "encryption")]
+    pub(crate) fn set_raw(&mut self, bytes: &[u8]) -> Result<()> {
+        self.check_lock()?;
+        if self.dry_run {
+            return Ok(());
+        }
+
+        self.check_fits(bytes.len() as u64)?;
+
+        self.file.rewind()?;
+        io::Write::write_all(&mut self.file, bytes)?;
+        self.file.set_len(bytes.len() as u64)?;
+
+        metrics::record(Metric::Write);
+        Ok(())
+    }
+
+    pub(crate) fn storage_file(&self) -> &S::File {
+        &self.file
+    }
+
+    #[cfg(feature = "encryption")]
+    pub(crate) fn file_mut(&mut self) -> &mut S::File {
+        &mut self.file
+    }
+
+    pub fn append(&mut self, authority: Authority) -> Result<()> {
+        if !self.should_write(&authority)? {
+            return Ok(());
+        }
+
+        // Holds without the append option on a file, as the file is opened locked
+        self.file.seek(io::SeekFrom::End(0))?;
+        authority.write_with(&mut self.file, &self.compat)?;
+        metrics::record(Metric::Write);
+
+        self.update_times(|timestamps| timestamps.record(authority.iter()))
+    }
+
+    fn update_times(&self, update: impl FnOnce(&mut Timestamps)) -> Result<()> {
+        if self.track_times
+            && let Some(path) = &self.path
+        {
+            let mut timestamps = Timestamps::load(path)?;
+            update(&mut timestamps);
+            timestamps.save()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn append_with(&mut self, authority: Authority, duplicates: Duplicates) -> Result<()> {
+        self.append_recorded(authority, duplicates).map(drop)
+    }
+
+    /// Like `append_with`, returning what changed, for `apply_revert`
+    pub fn append_recorded(
+        &mut self,
+        authority: Authority,
+        duplicates: Duplicates,
+    ) -> Result<ChangeSet> {
+        let existing = match duplicates {
+            Duplicates::Keep => {
+                let added = authority.iter().cloned().collect();
+                self.append(authority)?;
+                return Ok(ChangeSet::added(added));
+            }
+            _ => self.get()?,
+        };
+        let existing_keys: HashSet<_> = existing.iter().map(Entry::key).collect();
+
+        if duplicates == Duplicates::Skip {
+            let new: Vec<Entry> = authority
+                .into_iter()
+                .filter(|entry| !existing_keys.contains(&entry.key()))
+                .collect();
+
+            self.append(Authority::new(Some(new.clone())))?;
+            return Ok(ChangeSet::added(new));
+        }
+
+        let new_keys: HashSet<_> = authority.iter().map(Entry::key).collect();
+        let added: Vec<Entry> = authority.iter().cloned().collect();
+
+        // Only rewrite when something is actually shadowed
+        if existing_keys.is_disjoint(&new_keys) {
+            self.append(authority)?;
+            return Ok(ChangeSet::added(added));
+        }
+
+        let (removed, mut merged): (Vec<Entry>, Vec<Entry>) = existing
+            .into_iter()
+            .partition(|entry| new_keys.contains(&entry.key()));
+        merged.extend(authority);
+
+        self.set(Authority::new(Some(merged)))?;
+        Ok(ChangeSet { added, removed })
+    }
+
+    /// Like `set`, returning what changed, for `apply_revert`
+    pub fn set_recorded(&mut self, authority: Authority) -> Result<ChangeSet> {
+        let existing = self.get()?;
+
+        let removed = existing
+            .iter()
+            .filter(|entry| !authority.iter().any(|new| new == *entry))
+            .cloned()
+            .collect();
+        let added = authority
+            .iter()
+            .filter(|entry| !existing.iter().any(|old| old == *entry))
+            .cloned()
+            .collect();
+
+        self.set(authority)?;
+        Ok(ChangeSet { added, removed })
+    }
+
+    /// Undoes `changes`, leaving edits made by others since in place:
+    /// only the exact entries added are removed, and removed entries are only
+    /// restored if nothing took their place
+    pub fn apply_revert(&mut self, changes: ChangeSet) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let mut entries: Vec<Entry> = self.get()?.into_iter().collect();
+
+        for added in &changes.added {
+            if let Some(index) = entries.iter().position(|entry| entry == added) {
+                entries.remove(index);
+            }
+        }
+
+        let present: HashSet<_> = entries.iter().map(Entry::key).collect();
+        entries.extend(
+            changes
+                .removed
+                .into_iter()
+                .filter(|entry| !present.contains(&entry.key())),
+        );
+
+        self.set(Authority::new(Some(entries)))
+    }
+}
+
+/// Moves the entries of `src` that `filter` selects into `dst`, replacing entries there
+/// with the same key. Returns the moved entries.
+///
+/// Both files are locked throughout, see `MultiLock` for opening them.
+/// `dst` is written first: if removing from `src` then fails, entries are duplicated, never lost
+pub fn transfer<S: Storage, T: Storage>(
+    src: &mut AuthorityFile<S>,
+    dst: &mut AuthorityFile<T>,
+    filter: impl Fn(&Entry) -> bool,
+) -> Result<Vec<Entry>> {
+    let (moved, kept): (Vec<Entry>, Vec<Entry>) = src.get()?.into_iter().partition(filter);
+
+    if moved.is_empty() {
+        return Ok(moved);
+    }
+
+    dst.append_with(Authority::new(Some(moved.clone())), Duplicates::Replace)?;
+    src.set(Authority::new(Some(kept)))?;
+
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use super::*;
+    use crate::{MIT_MAGIC_COOKIE_1, encoding::Family, error::ErrorKind};
+
+    fn entry(display: u32) -> Entry {
+        Entry::builder()
+            .family(Family::Local)
+            .address(*b"host")
+            .display(display)
+            .protocol(MIT_MAGIC_COOKIE_1)
+            .data([display as u8; 16])
+            .build()
+            .unwrap()
+    }
+
+    fn entry_with(display: u32, data: u8) -> Entry {
+        Entry::builder()
+            .family(Family::Local)
+            .address(*b"host")
+            .display(display)
+            .protocol(MIT_MAGIC_COOKIE_1)
+            .data([data; 16])
+            .build()
+            .unwrap()
+    }
+
+    fn authority(displays: &[u32]) -> Authority {
+        Authority::new(Some(displays.iter().copied().map(entry).collect()))
+    }
+
+    fn displays(file: &mut AuthorityFile<impl Storage>) -> Vec<u32> {
+        file.get()
+            .unwrap()
+            .iter()
+            .map(|entry| entry.display().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn set_shrinks_the_file() {
+        let mut file = AuthorityFile::in_memory();
+        file.set(authority(&[5, 1, 2])).unwrap();
+        let long = file.to_bytes().len();
+
+        file.set(authority(&[5])).unwrap();
+
+        assert_eq!(displays(&mut file), [5]);
+        assert!(file.to_bytes().len() < long);
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("libxauth-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("Xauthority")
+    }
+
+    #[test]
+    fn prune_prefers_recorded_creation_times() {
+        let path = temp_path("prune-recorded");
+        let mut file = AuthorityFile::create(&path).unwrap();
+        file.record_creation_times(true);
+        file.append(authority(&[1, 2])).unwrap();
+
+        // Older than the entries' recorded times, which are now
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        file.as_file().set_modified(hour_ago).unwrap();
+
+        assert_eq!(file.prune_older_than(Duration::from_secs(60)).unwrap(), 0);
+        assert_eq!(displays(&mut file), [1, 2]);
+
+        drop(file);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn prune_judges_by_modification_time() {
+        let path = temp_path("prune");
+        let mut file = AuthorityFile::create(&path).unwrap();
+        file.set(authority(&[1, 2])).unwrap();
+
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        file.as_file().set_modified(hour_ago).unwrap();
+
+        assert_eq!(file.prune_older_than(Duration::from_secs(7200)).unwrap(), 0);
+        assert_eq!(file.prune_older_than(Duration::from_secs(60)).unwrap(), 2);
+        assert!(displays(&mut file).is_empty());
+
+        drop(file);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn apply_revert_undoes_replace() {
+        let mut file = AuthorityFile::in_memory();
+        file.set(Authority::new(Some(vec![entry_with(1, 0xaa), entry(2)])))
+            .unwrap();
+        let before = file.to_bytes();
+
+        let changes = file
+            .append_recorded(
+                Authority::new(Some(vec![entry_with(1, 0xbb)])),
+                Duplicates::Replace,
+            )
+            .unwrap();
+        assert_eq!(changes.added, [entry_with(1, 0xbb)]);
+        assert_eq!(changes.removed, [entry_with(1, 0xaa)]);
+
+        file.apply_revert(changes).unwrap();
+        let mut reverted: Vec<Entry> = file.get().unwrap().into_iter().collect();
+        reverted.sort_by_key(|entry| entry.display());
+        assert_eq!(reverted, [entry_with(1, 0xaa), entry(2)]);
+        assert_eq!(file.to_bytes().len(), before.len());
+    }
+
+    #[test]
+    fn apply_revert_keeps_edits_by_others() {
+        let mut file = AuthorityFile::in_memory();
+        file.set(authority(&[1])).unwrap();
+
+        let changes = file.set_recorded(authority(&[2])).unwrap();
+
+        // Someone else takes the removed entry's place, and adds one of their own
+        file.append(Authority::new(Some(vec![entry_with(1, 0xcc), entry(3)])))
+            .unwrap();
+
+        file.apply_revert(changes).unwrap();
+        let mut reverted: Vec<Entry> = file.get().unwrap().into_iter().collect();
+        reverted.sort_by_key(|entry| entry.display());
+        assert_eq!(reverted, [entry_with(1, 0xcc), entry(3)]);
+    }
+
+    #[test]
+    fn transfer_moves_selected_entries() {
+        let storage = MemoryStorage::new();
+        let mut src = AuthorityFile::create_in(&storage, Path::new("/src")).unwrap();
+        let mut dst = AuthorityFile::create_in(&storage, Path::new("/dst")).unwrap();
+        src.set(authority(&[1, 2, 3])).unwrap();
+        dst.set(Authority::new(Some(vec![entry_with(2, 0xdd), entry(4)])))
+            .unwrap();
+
+        let moved = transfer(&mut src, &mut dst, |entry| entry.display() != Some(1)).unwrap();
+
+        assert_eq!(moved, [entry(2), entry(3)]);
+        assert_eq!(displays(&mut src), [1]);
+        let mut dst_entries: Vec<Entry> = dst.get().unwrap().into_iter().collect();
+        dst_entries.sort_by_key(|entry| entry.display());
+        assert_eq!(dst_entries, [entry(2), entry(3), entry(4)]);
+    }
+
+    #[test]
+    fn transfer_of_nothing_leaves_both() {
+        let mut src = AuthorityFile::in_memory();
+        src.set(authority(&[1])).unwrap();
+        let mut dst = AuthorityFile::in_memory();
+
+        assert!(transfer(&mut src, &mut dst, |_| false).unwrap().is_empty());
+        assert_eq!(displays(&mut src), [1]);
+        assert!(dst.to_bytes().is_empty());
+    }
+
+    #[test]
+    fn memory_storage_lock_is_exclusive() {
+        let storage = MemoryStorage::new();
+        let path = Path::new("/Xauthority");
+        let file = AuthorityFile::create_in(&storage, path).unwrap();
+
+        let busy = AuthorityFile::open_in(&storage, path).err().unwrap();
+        assert_eq!(busy.kind(), ErrorKind::LockBusy);
+
+        drop(file);
+        assert!(AuthorityFile::open_in(&storage, path).is_ok());
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn open_with_retry_waits_for_mock_lock() {
+        use crate::mock::MockLock;
+
+        let path = temp_path("mock-retry");
+        drop(AuthorityFile::create(&path).unwrap());
+
+        let lock = MockLock::new().contended(2);
+        let storage = Fs::with_lock_strategy(lock.clone());
+        let retry = RetryPolicy::fixed(3, Duration::ZERO);
+
+        let file = AuthorityFile::open_with_retry_in(&storage, &path, retry).unwrap();
+        assert_eq!(lock.attempts(), 3);
+        assert!(lock.is_held());
+
+        drop(file);
+        assert!(!lock.is_held());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{encoding::RawEntry, parse::plausible_entry};
+
+/// What a cookie file most likely is, see `detect_format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Format {
+    /// Nothing to tell from. A valid, empty Xauthority as far as this crate is concerned
+    Empty,
+    /// The binary format this crate reads
+    Xauthority,
+    /// The hex text `xauth nlist` prints and `xauth nmerge` reads
+    XauthNlist,
+    /// The binary format of `~/.ICEauthority`, used by session managers
+    IceAuthority,
+    Unknown,
+}
+
+/// Guesses the format of `bytes`, so tools given "some cookie file" can pick a parser
+pub fn detect_format(bytes: &[u8]) -> Format {
+    if bytes.is_empty() {
+        return Format::Empty;
+    }
+
+    if is_nlist(bytes) {
+        return Format::XauthNlist;
+    }
+
+    if is_ice_authority(bytes) {
+        return Format::IceAuthority;
+    }
+
+    if is_xauthority(bytes) {
+        return Format::Xauthority;
+    }
+
+    Format::Unknown
+}
+
+// Lines of space-separated hex: family, then a length and data for each field
+fn is_nlist(bytes: &[u8]) -> bool {
+    let Ok(text) = str::from_utf8(bytes) else {
+        return false;
+    };
+
+    text.lines().filter(|line| !line.is_empty()).all(|line| {
+        let words: Vec<&str> = line.split(' ').collect();
+        words.len() == 9
+            && words[0].len() == 4
+            && words
+                .iter()
+                .all(|word| word.bytes().all(|byte| byte.is_ascii_hexdigit()))
+    })
+}
+
+// Length-prefixed protocol name, protocol data, network id, auth name and auth data,
+// with "ICE" as the protocol name in practice
+fn is_ice_authority(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"\x00\x03ICE") || bytes.starts_with(b"\x00\x04XSMP")
+}
+
+fn is_xauthority(bytes: &[u8]) -> bool {
+    if plausible_entry(bytes).is_none() {
+        return false;
+    }
+
+    // The rest need not be plausible, but should at least be well-formed
+    let mut rest = bytes;
+    loop {
+        match RawEntry::read_from(&mut rest) {
+            Ok(Some(_)) => {}
+            Ok(None) => return true,
+            Err(_) => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Entry, MIT_MAGIC_COOKIE_1, encoding::Family};
+
+    fn xauthority() -> Vec<u8> {
+        let entry = Entry::builder()
+            .family(Family::Local)
+            .address(*b"host")
+            .display(0)
+            .protocol(MIT_MAGIC_COOKIE_1)
+            .data([0x42; 16])
+            .build()
+            .unwrap();
+
+        let mut bytes = Vec::new();
+        entry.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn detects_formats() {
+        // The same entry as `xauthority`, as `xauth nlist` prints it
+        let nlist = "0100 0004 686f7374 0001 30 0012 4d49542d4d414749432d434f4f4b49452d31 \
+                     0010 42424242424242424242424242424242\n";
+
+        assert_eq!(detect_format(b""), Format::Empty);
+        assert_eq!(detect_format(&xauthority()), Format::Xauthority);
+        assert_eq!(detect_format(nlist.as_bytes()), Format::XauthNlist);
+        assert_eq!(detect_format(b"\x00\x03ICE\x00\x00"), Format::IceAuthority);
+        assert_eq!(detect_format(b"\x00\x04XSMP\x00\x00"), Format::IceAuthority);
+        assert_eq!(detect_format(b"not a cookie file"), Format::Unknown);
+    }
+
+    #[test]
+    fn truncated_xauthority_is_unknown() {
+        let mut bytes = xauthority();
+        bytes.pop();
+
+        assert_eq!(detect_format(&bytes), Format::Unknown);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    io,
+    os::{fd::AsFd, unix::fs::fchown},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Authority, Cookie,
+    beneath::Beneath,
+    display::DisplayName,
+    encoding::Entry,
+    env::DisplayEnv,
+    error::Result,
+    file::{AuthorityFile, CreateOptions},
+    key::EntryKey,
+    session::SessionAuthGuard,
+    shared_name,
+    storage::Storage,
+};
+
+const AUTHORITY_NAME: &str = "Xauthority";
+
+/// Authority setup for greetd-style greeters, which run as root (or a greeter user)
+/// and launch the X session as the user who logged in
+#[derive(Debug, Clone)]
+pub struct GreeterSession {
+    uid: u32,
+    gid: u32,
+    runtime_dir: Option<PathBuf>,
+}
+
+impl GreeterSession {
+    pub fn new(uid: u32, gid: u32) -> Self {
+        Self {
+            uid,
+            gid,
+            runtime_dir: None,
+        }
+    }
+
+    /// Where the authority is created. Defaults to `/run/user/<uid>`,
+    /// the `$XDG_RUNTIME_DIR` logind sets up for the user
+    pub fn runtime_dir(mut self, dir: &Path) -> Self {
+        self.runtime_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    fn dir(&self) -> PathBuf {
+        match &self.runtime_dir {
+            Some(dir) => dir.clone(),
+            None => PathBuf::from(format!("/run/user/{}", self.uid)),
+        }
+    }
+
+    /// Creates the user's authority with a single entry for `display`, owned by the user.
+    /// A leftover authority from a previous session is replaced
+    pub fn create(&self, display: &DisplayName, cookie: &Cookie) -> Result<SessionAuth> {
+        let dir = self.dir();
+        let path = dir.join(AUTHORITY_NAME);
+
+        // The user controls the directory and could plant symlinks to redirect our writes,
+        // so the file and its locks are only reached beneath it, never through a symlink
+        let storage = Beneath::open_dir(&dir)?.no_symlinks(true);
+        let name = Path::new(AUTHORITY_NAME);
+
+        // Never open what's there, a leftover could be anything
+        match storage.remove(name) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+
+        let mut file = CreateOptions::new().create_in(&storage, name)?;
+        // Guard before writing, so a failure below doesn't leave the file behind
+        let mut guard = SessionAuthGuard::new();
+        guard.remove_on_teardown(&path);
+        let session = SessionAuth {
+            path,
+            display: display.clone(),
+            guard,
+        };
+
+        let key = EntryKey::from_display_target(display)?;
+        let entry = Entry::from_parts(
+            key.family,
+            key.address,
+            key.display_number,
+            shared_name(cookie.name()),
+            // The secret stays within the library, nothing to report
+            cookie.bytes_unobserved().to_vec(),
+        );
+
+        file.set(Authority::new(Some(vec![entry])))?;
+        file.set_owner(self.uid, self.gid)?;
+
+        Ok(session)
+    }
+}
+
+/// The authority of a running session. Removed on drop, through its guard
+pub struct SessionAuth {
+    path: PathBuf,
+    display: DisplayName,
+    guard: SessionAuthGuard,
+}
+
+impl SessionAuth {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `XAUTHORITY` and `DISPLAY`, for the environment of the launched session
+    pub fn env(&self) -> [(&'static str, String); 2] {
+        self.display_env().to_env_pairs()
+    }
+
+    pub fn display_env(&self) -> DisplayEnv {
+        DisplayEnv::new(self.display.clone(), self.path.clone())
+    }
+
+    /// For registering more cleanup, e.g. the server's `-auth` file
+    pub fn guard_mut(&mut self) -> &mut SessionAuthGuard {
+        &mut self.guard
+    }
+
+    /// Like dropping, but reports failures
+    pub fn end(self) -> Result<()> {
+        self.guard.teardown()
+    }
+}
+
+impl<S: Storage> AuthorityFile<S>
+where
+    S::File: AsFd,
+{
+    /// Hands the file over to another user, e.g. the one a session is started for
+    pub fn set_owner(&self, uid: u32, gid: u32) -> Result<()> {
+        Ok(fchown(self.storage_file(), Some(uid), Some(gid))?)
+    }
+}
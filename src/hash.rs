@@ -0,0 +1,46 @@
+use std::io::{self, Write};
+
+use crate::encoding::Entry;
+
+// FNV-1a, chosen over std's DefaultHasher because its output is specified
+// and won't change between compiler releases
+pub struct Fnv1a(u64);
+
+impl Fnv1a {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    pub fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Write for Fnv1a {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Hash of the encoded entry, stable across releases
+pub(crate) fn hash_entry(entry: &Entry) -> u64 {
+    let mut hasher = Fnv1a::new();
+    // Writing to the hasher can't fail
+    let _ = entry.encode_to(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,88 @@
+use std::{io, path::Path};
+
+use crate::{error::Result, file::AuthorityFile};
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" {
+    fn setfsuid(uid: u32) -> i32;
+    fn setfsgid(gid: u32) -> i32;
+}
+
+/// Filesystem identity of another user, for root display managers touching
+/// files in the user's home: with it, a symlink the user planted can only lead
+/// to files the user could access anyway.
+///
+/// Linux only. The identity is per thread, so anything spawned inside `run`,
+/// like a lock heartbeat, keeps the original one. Supplementary groups aren't changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsIdentity {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Restores the previous identity, also when unwinding
+#[cfg(target_os = "linux")]
+struct Restore {
+    uid: u32,
+    gid: u32,
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Restore {
+    fn drop(&mut self) {
+        unsafe {
+            setfsuid(self.uid);
+            setfsgid(self.gid);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn current() -> (u32, u32) {
+    // An invalid id changes nothing, but still reports the current one
+    unsafe { (setfsuid(u32::MAX) as u32, setfsgid(u32::MAX) as u32) }
+}
+
+impl FsIdentity {
+    pub fn new(uid: u32, gid: u32) -> Self {
+        Self { uid, gid }
+    }
+
+    /// Runs `f` with this filesystem identity on the current thread
+    #[cfg(target_os = "linux")]
+    pub fn run<T>(&self, f: impl FnOnce() -> T) -> io::Result<T> {
+        let (uid, gid) = current();
+        let _restore = Restore { uid, gid };
+
+        // Group first, dropping the uid may take the right to change it
+        unsafe {
+            setfsgid(self.gid);
+            setfsuid(self.uid);
+        }
+
+        // Neither call reports failure directly
+        if current() != (self.uid, self.gid) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "can't change the filesystem identity",
+            ));
+        }
+
+        Ok(f())
+    }
+
+    /// Fails with `Unsupported`: other systems have no per-thread filesystem identity
+    #[cfg(not(target_os = "linux"))]
+    pub fn run<T>(&self, _f: impl FnOnce() -> T) -> io::Result<T> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "filesystem identities are Linux only",
+        ))
+    }
+
+    /// Opens the user's authority at `path` as the user, creating it if missing.
+    /// The file keeps being usable after switching back
+    pub fn open_or_create(&self, path: &Path) -> Result<AuthorityFile> {
+        self.run(|| AuthorityFile::open_or_create(path))?
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    ffi::CStr,
+    io,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    Authority, Hostname, MIT_MAGIC_COOKIE_1,
+    display::DisplayName,
+    encoding::{Entry, Family},
+    family_codes::{FAMILY_INTERNET, FAMILY_INTERNET6},
+};
+
+/// The part of an entry identifying which connections it's for.
+/// Two entries with the same key are duplicates, and only the first is ever used
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EntryKey {
+    pub family: Family,
+    pub address: Vec<u8>,
+    pub display_number: String,
+    pub auth_name: String,
+}
+
+static NAME_RESOLUTION: AtomicBool = AtomicBool::new(true);
+
+/// Like `xauth -n` when disabled: host names in display names are never resolved,
+/// so lookups can't block on the resolver. Displays naming a host rather than
+/// an address then fail to look up. Process-wide, like `set_metrics_observer`
+pub fn set_name_resolution(enabled: bool) {
+    NAME_RESOLUTION.store(enabled, Ordering::Relaxed);
+}
+
+pub fn name_resolution() -> bool {
+    NAME_RESOLUTION.load(Ordering::Relaxed)
+}
+
+/// `host` as it may be passed to the resolver: as-is, or while name resolution is off,
+/// only if it's an address already
+pub(crate) fn resolvable_host(host: &str) -> io::Result<&str> {
+    if name_resolution() {
+        return Ok(host);
+    }
+
+    // Only in the hosts file, but common enough for forwarded displays to be worth it
+    let host = match host {
+        "localhost" => "127.0.0.1",
+        host => host,
+    };
+
+    match host.parse::<IpAddr>() {
+        Ok(_) => Ok(host),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{host} is not an address, and name resolution is off"),
+        )),
+    }
+}
+
+unsafe extern "C" {
+    fn gethostname(name: *mut u8, len: usize) -> i32;
+}
+
+pub(crate) fn local_hostname() -> io::Result<Hostname> {
+    let mut buf = [0u8; 256];
+
+    if unsafe { gethostname(buf.as_mut_ptr(), buf.len()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Truncated names may lack the terminator
+    let name = CStr::from_bytes_until_nul(&buf).map_or(&buf[..], CStr::to_bytes);
+    Ok(name.to_vec())
+}
+
+impl EntryKey {
+    /// Key for a TCP connection to `addr`, with MIT-MAGIC-COOKIE-1.
+    /// Set `auth_name` for other protocols
+    pub fn from_socket_addr(addr: SocketAddr, display: u32) -> Self {
+        let (family, address) = match addr.ip() {
+            IpAddr::V4(ip) => (Family::Other(FAMILY_INTERNET), ip.octets().to_vec()),
+            // Servers see these as plain IPv4 connections
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => (Family::Other(FAMILY_INTERNET), ip.octets().to_vec()),
+                None => (Family::Other(FAMILY_INTERNET6), ip.octets().to_vec()),
+            },
+        };
+
+        Self {
+            family,
+            address,
+            display_number: display.to_string(),
+            auth_name: MIT_MAGIC_COOKIE_1.to_string(),
+        }
+    }
+
+    /// Key for connecting to `target`, with MIT-MAGIC-COOKIE-1 like `from_socket_addr`.
+    /// Resolves the host name if needed, see `set_name_resolution`
+    pub fn from_display_target(target: &DisplayName) -> io::Result<Self> {
+        if target.is_local() {
+            return Ok(Self {
+                family: Family::Local,
+                address: local_hostname()?,
+                display_number: target.display.to_string(),
+                auth_name: MIT_MAGIC_COOKIE_1.to_string(),
+            });
+        }
+
+        let host = resolvable_host(&target.host)?;
+
+        // X servers listen on 6000 + display
+        let port = 6000u16.saturating_add(target.display.try_into().unwrap_or(u16::MAX));
+
+        let addr = (host, port).to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no addresses", target.host),
+            )
+        })?;
+
+        Ok(Self::from_socket_addr(addr, target.display))
+    }
+}
+
+impl Entry {
+    pub fn key(&self) -> EntryKey {
+        EntryKey {
+            family: self.family,
+            address: self.address.clone(),
+            display_number: self.display_number.clone(),
+            auth_name: self.auth_name.to_string(),
+        }
+    }
+
+    pub fn has_key(&self, key: &EntryKey) -> bool {
+        self.is_for(key) && *self.auth_name == key.auth_name
+    }
+
+    /// Like `has_key`, whatever the protocol
+    pub(crate) fn is_for(&self, key: &EntryKey) -> bool {
+        self.family == key.family
+            && self.address == key.address
+            && self.display_number == key.display_number
+    }
+}
+
+/// Lookups by exact key, without the wildcard matching of `get_best`
+impl Authority {
+    pub fn contains(&self, key: &EntryKey) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn get(&self, key: &EntryKey) -> Option<&Entry> {
+        self.iter().find(|entry| entry.has_key(key))
+    }
+
+    pub fn get_mut(&mut self, key: &EntryKey) -> Option<&mut Entry> {
+        self.0.iter_mut().find(|entry| entry.has_key(key))
+    }
+}
@@ -0,0 +1,143 @@
+use std::{ffi::CString, fmt::Write, io};
+
+use crate::{Authority, encoding::Entry, shared_name};
+
+/// Where the cookies of reference entries are kept, e.g. the kernel keyring
+/// or a Secret Service implementation
+pub trait CookieStore {
+    /// Stores `cookie`, returning the reference written to the authority in its place
+    fn store(&self, description: &str, cookie: &[u8]) -> io::Result<Vec<u8>>;
+    fn fetch(&self, reference: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// Protocol names of reference entries are the real one with this prefix.
+/// Clients unaware of references skip them, as they don't know the protocol
+pub const REFERENCE_PREFIX: &str = "X-REF/";
+
+impl Entry {
+    /// A copy of this entry with the cookie moved into `store`,
+    /// to be written to the file instead of the cookie itself.
+    /// Stored as `xauth:<family>:<hex address>:<display>:<protocol>`
+    pub fn to_reference(&self, store: &impl CookieStore) -> io::Result<Entry> {
+        // Unique per entry: storing under an existing description replaces that cookie
+        let mut description = format!("xauth:{}:", self.family.code());
+        for byte in &self.address {
+            let _ = write!(description, "{byte:02x}");
+        }
+        let _ = write!(description, ":{}:{}", self.display_number, self.auth_name);
+
+        let mut entry = self.clone();
+        entry.replace_auth_data(store.store(&description, self.auth_data())?);
+        entry.auth_name = format!("{REFERENCE_PREFIX}{}", self.auth_name).into();
+        Ok(entry)
+    }
+
+    pub fn is_reference(&self) -> bool {
+        self.auth_name.starts_with(REFERENCE_PREFIX)
+    }
+}
+
+impl Authority {
+    /// Fetches the cookies of reference entries from `store`, at connection time.
+    /// Other entries are kept as they are
+    pub fn resolve_references(&self, store: &impl CookieStore) -> io::Result<Authority> {
+        let entries = self
+            .iter()
+            .map(|entry| {
+                let Some(name) = entry.auth_name.strip_prefix(REFERENCE_PREFIX) else {
+                    return Ok(entry.clone());
+                };
+
+                let mut resolved = entry.clone();
+                resolved.replace_auth_data(store.fetch(entry.auth_data_unobserved())?);
+                resolved.auth_name = shared_name(name);
+                Ok(resolved)
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Authority::new(Some(entries)))
+    }
+}
+
+unsafe extern "C" {
+    fn syscall(number: i64, ...) -> i64;
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const SYSCALLS: Option<(i64, i64)> = Some((248, 250));
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+const SYSCALLS: Option<(i64, i64)> = Some((217, 219));
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+const SYSCALLS: Option<(i64, i64)> = None;
+
+const KEY_SPEC_USER_KEYRING: i32 = -4;
+const KEYCTL_READ: i64 = 11;
+
+/// The Linux kernel keyring: cookies are stored as `user` keys in the user keyring,
+/// referenced by their serial number
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KernelKeyring;
+
+impl KernelKeyring {
+    fn syscalls() -> io::Result<(i64, i64)> {
+        SYSCALLS.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "no kernel keyring on this platform",
+            )
+        })
+    }
+}
+
+impl CookieStore for KernelKeyring {
+    fn store(&self, description: &str, cookie: &[u8]) -> io::Result<Vec<u8>> {
+        let (add_key, _) = Self::syscalls()?;
+        let description = CString::new(description)?;
+
+        let serial = unsafe {
+            syscall(
+                add_key,
+                c"user".as_ptr(),
+                description.as_ptr(),
+                cookie.as_ptr(),
+                cookie.len(),
+                KEY_SPEC_USER_KEYRING,
+            )
+        };
+
+        if serial < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(serial.to_string().into_bytes())
+    }
+
+    fn fetch(&self, reference: &[u8]) -> io::Result<Vec<u8>> {
+        let (_, keyctl) = Self::syscalls()?;
+        let serial: i32 = str::from_utf8(reference)
+            .ok()
+            .and_then(|serial| serial.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid key serial"))?;
+
+        // Cookies are small, but ask for the size to be safe
+        let mut buf = vec![0u8; 64];
+        loop {
+            let len = unsafe { syscall(keyctl, KEYCTL_READ, serial, buf.as_mut_ptr(), buf.len()) };
+
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let len = len as usize;
+            if len <= buf.len() {
+                buf.truncate(len);
+                return Ok(buf);
+            }
+
+            buf.resize(len, 0);
+        }
+    }
+}
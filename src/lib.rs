@@ -1,16 +1,125 @@
+mod archive;
+mod audit;
+mod batch;
+mod beneath;
+#[cfg(feature = "broker")]
+mod broker;
+mod builder;
+mod compat;
+mod display;
 mod encoding;
+#[cfg(feature = "encryption")]
+mod encrypted;
+mod env;
+mod error;
+pub mod family_codes;
+mod file;
+mod format;
+mod greeter;
+mod hash;
+mod identity;
+mod key;
+#[cfg(feature = "keyring")]
+mod keyring;
 mod lock;
+mod lookup;
+mod metrics;
+mod migrate;
+#[cfg(feature = "test-util")]
+mod mock;
+mod multilock;
+mod ofd;
+mod parse;
+mod probe;
+mod provision;
+mod proxy;
+mod report;
+mod retry;
+mod scan;
+mod script;
+#[cfg(feature = "hardened-memory")]
+mod secret;
+mod server;
+mod service;
+mod session;
+mod storage;
+mod stream;
+mod timestamps;
+mod xdmcp;
 
 use std::{
-    fs::{File, OpenOptions},
-    io::{self, Read, Seek, Write},
-    os::unix::fs::OpenOptionsExt,
-    path::Path,
-    vec,
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::File,
+    io::{self, Read, Write},
+    os::{fd::BorrowedFd, unix::fs::FileExt},
+    slice, vec,
 };
 
-use crate::encoding::Family;
-pub use crate::{encoding::Entry, lock::Lock};
+pub use crate::{
+    archive::{Archive, ArchivedAuthority},
+    audit::{SecretAccess, SecretAccessObserver, set_secret_access_observer},
+    batch::Batch,
+    beneath::{Beneath, BeneathFile, BeneathLock},
+    builder::EntryBuilder,
+    compat::CompatOptions,
+    display::DisplayName,
+    encoding::{Entry, Family},
+    env::DisplayEnv,
+    error::{Error, ErrorKind, FieldTooLong, Result},
+    file::{
+        AuthorityFile, AuthorityInfo, ChangeSet, CreateOptions, Created, Duplicates, Expiry,
+        FileMode, LockDisposition, OpenMode, Snapshot, transfer,
+    },
+    format::{Format, detect_format},
+    greeter::{GreeterSession, SessionAuth},
+    identity::FsIdentity,
+    key::{EntryKey, name_resolution, set_name_resolution},
+    lock::{DotLock, Lock, LockGuard, LockOptions, LockState, LockStrategy, StaleLockPolicy},
+    lookup::{BestMatch, ConnectionKind, DEFAULT_PROTOCOLS, XCB_PROTOCOLS},
+    metrics::{Metric, MetricsObserver, set_metrics_observer},
+    migrate::{Migration, migrate_between, migrate_to_runtime_dir},
+    multilock::MultiLock,
+    ofd::{OfdLock, OfdLockGuard},
+    parse::{ParseOptions, ParseWarning, Parsed, Utf8Policy, WarningReason},
+    probe::ServerVerdict,
+    provision::ProvisioningPacket,
+    proxy::{ProxyAuth, Rewrite, SetupRequest},
+    report::{AuditOutput, InfoOutput, ListEntry, ListOutput},
+    retry::{Backoff, RetryPolicy},
+    scan::{EntryRef, EntryScanner},
+    server::{ServerAuth, ServerAuthBuilder, verify_pair},
+    service::{AuthorityService, ServiceOptions, Ticket},
+    session::SessionAuthGuard,
+    storage::{
+        Detached, Fs, MemoryFile, MemoryLock, MemoryStorage, NoLock, Storage, StorageFile, Stream,
+    },
+    stream::{AppendWriter, AuthorityReader, AuthorityWriter},
+    xdmcp::XdmcpAuthorization,
+};
+use crate::{
+    encoding::{Interner, read_entry, shared_name},
+    hash::{Fnv1a, hash_entry},
+};
+
+#[cfg(feature = "broker")]
+pub use crate::broker::{
+    BUS_NAME, Broker, CookieReply, CookieRequest, GET_COOKIE, INTERFACE, OBJECT_PATH,
+};
+#[cfg(feature = "encryption")]
+pub use crate::encrypted::{EncryptedAuthority, Sealer};
+#[cfg(feature = "keyring")]
+pub use crate::keyring::{CookieStore, KernelKeyring, REFERENCE_PREFIX};
+#[cfg(feature = "test-util")]
+pub use crate::mock::{MockLock, MockLockGuard, MockOutcome};
+#[cfg(feature = "hardened-memory")]
+pub use crate::secret::SecretBuf;
+
+// Auth protocol names, as they appear in entries and the connection setup
+pub const MIT_MAGIC_COOKIE_1: &str = "MIT-MAGIC-COOKIE-1";
+pub const XDM_AUTHORIZATION_1: &str = "XDM-AUTHORIZATION-1";
+pub const SUN_DES_1: &str = "SUN-DES-1";
+pub const MIT_KERBEROS_5: &str = "MIT-KERBEROS-5";
 
 pub type Hostname = Vec<u8>;
 
@@ -29,6 +138,7 @@ impl From<Target> for String {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Scope {
     Local(Hostname),
     Any,
@@ -38,45 +148,146 @@ impl From<Scope> for (Family, Hostname) {
     fn from(value: Scope) -> Self {
         match value {
             Scope::Local(hostname) => (Family::Local, hostname),
-            Scope::Any => (Family::Wild, [127, 0, 0, 2].to_vec()), // TODO: address
+            // The address of wild entries is ignored, see CompatOptions for legacy readers
+            Scope::Any => (Family::Wild, Vec::new()),
         }
     }
 }
 
 // Technically, this should be a trait "AuthMethod"
 // Practically, cookie is the only method that is currently used
-// TODO: do we need special memory handling here for security? zeroize on drop?
-pub struct Cookie([u8; Self::BYTES_LEN]);
+// With the hardened-memory feature, cookie bytes are kept in a SecretBuf
+pub struct Cookie(CookieBytes);
+
+#[cfg(not(feature = "hardened-memory"))]
+type CookieBytes = [u8; Cookie::BYTES_LEN];
+#[cfg(feature = "hardened-memory")]
+type CookieBytes = SecretBuf;
+
 impl Cookie {
     pub const BYTES_LEN: usize = 16; // 16 * 8 = 128 random bits
-    const AUTH_NAME: &str = "MIT-MAGIC-COOKIE-1";
 
+    #[cfg(not(feature = "hardened-memory"))]
     pub fn new(random_bytes: [u8; Self::BYTES_LEN]) -> Self {
         Self(random_bytes)
     }
 
+    #[cfg(feature = "hardened-memory")]
+    pub fn new(mut random_bytes: [u8; Self::BYTES_LEN]) -> Self {
+        let cookie = Self(SecretBuf::new(&random_bytes));
+
+        // Don't leave a copy behind on the stack
+        for byte in &mut random_bytes {
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+
+        cookie
+    }
+
+    /// A cookie derived from `seed`, for asserting on exact file contents in tests.
+    ///
+    /// **Not for production**: anyone who knows the seed knows the cookie
+    #[cfg(feature = "test-util")]
+    pub fn deterministic(seed: u64) -> Self {
+        // splitmix64
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
+        };
+
+        let mut bytes = [0u8; Self::BYTES_LEN];
+        for chunk in bytes.chunks_mut(8) {
+            chunk.copy_from_slice(&next().to_be_bytes()[..chunk.len()]);
+        }
+
+        Self::new(bytes)
+    }
+
+    /// Allocates, see `name` and `bytes` for hot paths
     pub fn raw_data(&self) -> (String, Vec<u8>) {
-        // TODO: return &str for name?
-        (Self::AUTH_NAME.to_string(), self.0.into())
+        (self.name().to_string(), self.bytes().to_vec())
+    }
+
+    pub fn name(&self) -> &'static str {
+        MIT_MAGIC_COOKIE_1
+    }
+
+    pub fn bytes(&self) -> &[u8; Self::BYTES_LEN] {
+        audit::notify(SecretAccess::RawData, MIT_MAGIC_COOKIE_1);
+        self.bytes_unobserved()
+    }
+
+    pub(crate) fn bytes_unobserved(&self) -> &[u8; Self::BYTES_LEN] {
+        self.0[..].try_into().expect("cookies are BYTES_LEN long")
+    }
+
+    /// The cookie as lowercase hex, the way `xauth list` prints it
+    pub fn to_hex(&self) -> String {
+        format!("{self:x}")
+    }
+
+    fn fmt_hex(&self, f: &mut fmt::Formatter, upper: bool) -> fmt::Result {
+        audit::notify(SecretAccess::RawData, MIT_MAGIC_COOKIE_1);
+
+        for byte in self.0.iter() {
+            match upper {
+                true => write!(f, "{byte:02X}")?,
+                false => write!(f, "{byte:02x}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Redacted, so cookies don't end up in logs by accident.
+/// Format with `{:x}` to print the actual bytes
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{MIT_MAGIC_COOKIE_1} <redacted>")
+    }
+}
+
+impl fmt::LowerHex for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_hex(f, false)
+    }
+}
+
+impl fmt::UpperHex for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_hex(f, true)
     }
 }
 
 impl Entry {
     pub fn new(cookie: &Cookie, scope: Scope, target: Target) -> Entry {
         let (family, address) = scope.into();
-        let display_number = target.into();
-        let (auth_name, auth_data) = cookie.raw_data();
-
-        Entry {
+        let display_number: String = target.into();
+        Entry::from_parts(
             family,
             address,
             display_number,
-            auth_name,
-            auth_data,
-        }
+            shared_name(cookie.name()),
+            // The secret stays within the library, nothing to report
+            cookie.bytes_unobserved().to_vec(),
+        )
     }
 }
 
+#[derive(Debug, Default)]
+pub struct AuthorityStats {
+    pub entries: usize,
+    pub per_family: HashMap<Family, usize>,
+    pub per_protocol: HashMap<String, usize>,
+    pub distinct_displays: usize,
+    pub encoded_size: usize,
+}
+
 pub struct Authority(Vec<Entry>);
 
 impl Authority {
@@ -88,94 +299,155 @@ impl Authority {
         self.0.push(entry);
     }
 
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
-        let mut buf = Vec::new();
+    pub fn iter(&self) -> slice::Iter<'_, Entry> {
+        self.0.iter()
+    }
 
-        while let Some(entry) = Entry::read_from(reader)? {
-            buf.push(entry);
-        }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
 
-        Ok(Self(buf))
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 
-    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    /// Stable hash of the encoded entries, in order.
+    /// Equal to hashing the bytes `AuthorityFile::set` would write by default
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = Fnv1a::new();
+
         for entry in &self.0 {
-            entry.write_to(writer)?
+            // Writing to the hasher can't fail
+            let _ = entry.encode_to(&mut hasher);
         }
 
-        Ok(())
+        hasher.finish()
     }
-}
 
-impl IntoIterator for Authority {
-    type Item = Entry;
-    type IntoIter = vec::IntoIter<Self::Item>;
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+    /// Like `content_hash`, but independent of entry order
+    pub fn content_hash_unordered(&self) -> u64 {
+        let mut entry_hashes: Vec<u64> = self.0.iter().map(hash_entry).collect();
+
+        entry_hashes.sort_unstable();
+
+        let mut hasher = Fnv1a::new();
+        for hash in entry_hashes {
+            hasher.update(&hash.to_be_bytes());
+        }
+
+        hasher.finish()
     }
-}
 
-pub struct AuthorityFile {
-    file: File,
-    _lock: Option<Lock>,
-}
+    /// Compares entries as multisets, ignoring order
+    pub fn eq_unordered(&self, other: &Self) -> bool {
+        if self.0.len() != other.0.len() {
+            return false;
+        }
+
+        let mut counts: HashMap<&Entry, isize> = HashMap::new();
+
+        for entry in &self.0 {
+            *counts.entry(entry).or_default() += 1;
+        }
+
+        for entry in &other.0 {
+            match counts.get_mut(entry) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => return false,
+            }
+        }
 
-impl AuthorityFile {
-    pub fn from_existing(file: File, lock: Lock) -> io::Result<Self> {
-        Ok(Self {
-            file,
-            _lock: Some(lock),
-        })
+        true
     }
 
-    /// # Safety
-    /// the caller should ensure no other process will open the same file
-    /// Note that for files created by other programs, this is generraly impossible to guarantee
-    /// Thus, this api is not recommended, unless you are absolutely sure what you're doing
-    pub unsafe fn from_existing_unlocked(file: File) -> Self {
-        Self { file, _lock: None }
+    /// Adds the entries of `other`, replacing those with the same key
+    pub fn merge(&mut self, other: Authority) {
+        let replaced: HashSet<_> = other.iter().map(Entry::key).collect();
+        self.0.retain(|entry| !replaced.contains(&entry.key()));
+        self.0.extend(other);
     }
 
-    fn create_inner(path: &Path) -> io::Result<File> {
-        OpenOptions::new()
-            .read(true)
-            .write(true)
-            .mode(0o600)
-            .create_new(true)
-            .open(path)
+    /// Removes every entry whose key appears in `other`, whatever its protocol or cookie
+    pub fn subtract(&mut self, other: &Authority) {
+        let keys: Vec<EntryKey> = other.iter().map(Entry::key).collect();
+        self.0
+            .retain(|entry| !keys.iter().any(|key| entry.is_for(key)));
     }
 
-    pub fn create(path: &Path) -> io::Result<Self> {
-        let file = Self::create_inner(path)?;
-        let lock = Lock::aqquire(path)?;
+    pub fn stats(&self) -> AuthorityStats {
+        let mut stats = AuthorityStats::default();
+        let mut displays = HashSet::new();
+
+        for entry in &self.0 {
+            stats.entries += 1;
+            *stats.per_family.entry(entry.family).or_default() += 1;
+            *stats
+                .per_protocol
+                .entry(entry.auth_name.to_string())
+                .or_default() += 1;
+            displays.insert(entry.display_number.as_str());
+            stats.encoded_size += entry.encoded_len();
+        }
 
-        Ok(Self {
-            file,
-            _lock: Some(lock),
-        })
+        stats.distinct_displays = displays.len();
+        stats
     }
 
-    /// # Safety
-    /// the caller should ensure no other process will open the same path
-    // TODO: add examples on how to guarantee that
-    pub unsafe fn create_unlocked(path: &Path) -> io::Result<Self> {
-        let file = Self::create_inner(path)?;
-        Ok(Self { file, _lock: None })
+    /// Reads the whole file behind `fd`, e.g. one passed over a unix socket.
+    /// The descriptor's file offset is left unchanged
+    pub fn read_from_fd(fd: BorrowedFd<'_>) -> io::Result<Self> {
+        let file = File::from(fd.try_clone_to_owned()?);
+
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            // Positional reads, as the duplicate shares its offset with `fd`
+            match file.read_at(&mut chunk, buf.len() as u64) {
+                Ok(0) => break,
+                Ok(len) => buf.extend_from_slice(&chunk[..len]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Self::read_from(&mut buf.as_slice())
     }
 
-    pub fn get(&mut self) -> io::Result<Authority> {
-        self.file.rewind()?;
-        Authority::read_from(&mut self.file)
+    pub(crate) fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut buf = Vec::new();
+        let mut interner = Interner::new();
+
+        while let Some(entry) = read_entry(reader, &mut interner)? {
+            buf.push(entry);
+        }
+
+        Ok(Self(buf))
     }
 
-    pub fn set(&mut self, authority: Authority) -> io::Result<()> {
-        self.file.rewind()?;
-        authority.write_to(&mut self.file)
+    pub(crate) fn write_with<W: Write>(
+        &self,
+        writer: &mut W,
+        compat: &CompatOptions,
+    ) -> io::Result<()> {
+        // Check every entry upfront, so neither an oversized field
+        // nor a veto can leave a half-written file
+        for entry in &self.0 {
+            entry.check_lengths()?;
+            audit::check(SecretAccess::Serialize, &entry.auth_name)?;
+        }
+
+        for entry in &self.0 {
+            compat.apply(entry).encode_to(writer)?
+        }
+
+        Ok(())
     }
+}
 
-    pub fn append(&mut self, authority: Authority) -> io::Result<()> {
-        // Holds without the append option on a file, as the file is opened locked
-        self.file.seek(io::SeekFrom::End(0))?;
-        authority.write_to(&mut self.file)
+impl IntoIterator for Authority {
+    type Item = Entry;
+    type IntoIter = vec::IntoIter<Self::Item>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
     }
 }
@@ -1,54 +1,760 @@
 use std::{
-    fs::{OpenOptions, hard_link, remove_file},
-    io,
-    os::unix::fs::OpenOptionsExt,
+    fmt,
+    fs::{self, OpenOptions, hard_link, remove_file},
+    io::{self, Write},
+    os::unix::fs::{MetadataExt, OpenOptionsExt},
     path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
 };
 
+use crate::{Hostname, key::local_hostname, retry::RetryPolicy};
+
 fn replace_filename(mut path: PathBuf, new_filename: String) -> PathBuf {
     path.set_file_name(new_filename);
     path
 }
 
-// TODO: stale lock removal
+const EPERM: i32 = 1;
+const EOPNOTSUPP: i32 = 95;
+
+/// `link` failing this way means the filesystem has no hard links, rather than a busy lock.
+/// libXau then makes do with the exclusively created `-c` file alone
+pub(crate) fn links_unsupported(error: &io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(EPERM | EOPNOTSUPP))
+}
+
+pub(crate) fn lock_paths(xauth_path: &Path) -> io::Result<(PathBuf, PathBuf)> {
+    let filename = xauth_path.file_name().ok_or(io::Error::new(
+        io::ErrorKind::InvalidFilename,
+        "xauth_path does not end with a file",
+    ))?;
+    let filename = filename.to_str().unwrap(); // TODO: error
+
+    let creat_path = replace_filename(xauth_path.to_path_buf(), format!("{filename}-c"));
+    let link_path = replace_filename(xauth_path.to_path_buf(), format!("{filename}-l"));
+
+    Ok((creat_path, link_path))
+}
+
+/// Tells lock files apart. Inode numbers are often reused as soon as a lock is removed,
+/// so the holder record is compared as well
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LockId {
+    inode: (u64, u64),
+    record: Vec<u8>,
+}
+
+impl LockId {
+    /// None if there is no file at `path`. Symlinks aren't followed
+    fn of(path: &Path) -> io::Result<Option<Self>> {
+        let not_found = |e: io::Error| match e.kind() {
+            io::ErrorKind::NotFound => Ok(None),
+            _ => Err(e),
+        };
+
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) => return not_found(e),
+        };
+
+        let record = match metadata.is_file() {
+            true => match fs::read(path) {
+                Ok(record) => record,
+                Err(e) => return not_found(e),
+            },
+            false => Vec::new(),
+        };
+
+        Ok(Some(Self {
+            inode: (metadata.dev(), metadata.ino()),
+            record,
+        }))
+    }
+}
+
+static ASIDE_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// Removes `path` if it is still the lock file `id` identifies. It is moved aside before
+/// checking, so a file someone else put there meanwhile is moved back instead of removed
+fn remove_if_same(path: &Path, id: &LockId) -> io::Result<bool> {
+    let mut aside = path.as_os_str().to_owned();
+    aside.push(format!(
+        ".{}-{}",
+        process::id(),
+        ASIDE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let aside = PathBuf::from(aside);
+
+    match fs::rename(path, &aside) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    }
+
+    if LockId::of(&aside)?.as_ref() == Some(id) {
+        remove_file(&aside)?;
+        return Ok(true);
+    }
+
+    // A link doesn't replace yet another file that may have shown up
+    match hard_link(&aside, path) {
+        Err(e) if links_unsupported(&e) => fs::rename(&aside, path)?,
+        _ => remove_file(&aside)?,
+    }
+
+    Ok(false)
+}
+
+/// What lock files record for liveness checks by whoever finds them stale:
+/// the holder's PID, then the host it runs on
+pub(crate) fn holder_record() -> Vec<u8> {
+    let mut record = process::id().to_string().into_bytes();
+
+    if let Ok(host) = local_hostname() {
+        record.push(b' ');
+        record.extend(host);
+    }
+
+    record
+}
+
+unsafe extern "C" {
+    fn kill(pid: i32, sig: i32) -> i32;
+}
+
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = i32::try_from(pid) else {
+        return false;
+    };
+
+    // Signal 0 only checks whether the process can be signalled
+    if unsafe { kill(pid, 0) } == 0 {
+        return true;
+    }
+
+    // EPERM: exists, but belongs to someone else
+    io::Error::last_os_error().kind() == io::ErrorKind::PermissionDenied
+}
+
+/// When a leftover lock may be removed
+#[derive(Debug, Clone, Copy)]
+pub struct StaleLockPolicy {
+    /// Locks whose files weren't modified for longer than this are stale
+    pub max_age: Duration,
+    /// Keep the lock if the recorded holder process is still running.
+    /// Only holders on this host are checked. Wrong where lock users share
+    /// a host name but not a PID namespace, like containers with a bind mounted home
+    pub check_holder: bool,
+}
+
+impl Default for StaleLockPolicy {
+    fn default() -> Self {
+        Self {
+            max_age: Duration::from_secs(600),
+            check_holder: true,
+        }
+    }
+}
+
+/// How `Lock::acquire_with` waits for a busy lock, like `XauLockAuth`'s retries and timeout
+#[derive(Debug, Clone, Copy)]
+pub struct LockOptions {
+    /// Applied while the lock is busy
+    pub retry: RetryPolicy,
+    /// As in `Lock::aqquire_with`
+    pub stale: Option<StaleLockPolicy>,
+}
+
+impl Default for LockOptions {
+    /// What xauth uses: 10 retries, a second apart
+    fn default() -> Self {
+        Self {
+            retry: RetryPolicy::fixed(11, Duration::from_secs(1)),
+            stale: Some(StaleLockPolicy::default()),
+        }
+    }
+}
+
+/// Payload of the `AlreadyExists` io error returned for locks that look abandoned
+#[derive(Debug)]
+pub(crate) struct StaleLock {
+    pub age: Option<Duration>,
+}
+
+impl StaleLock {
+    pub fn error(age: Option<Duration>) -> io::Error {
+        io::Error::new(io::ErrorKind::AlreadyExists, Self { age })
+    }
+
+    /// `Some` if `error` was created by `StaleLock::error`
+    pub fn from_error(error: &io::Error) -> Option<&Self> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for StaleLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("lock is held, but looks abandoned")
+    }
+}
+
+impl std::error::Error for StaleLock {}
+
+/// How `AuthorityFile` keeps other processes away from the file it's using:
+/// `DotLock` for the scheme libXau uses, `OfdLock` for kernel enforced locks
+#[doc(alias = "LockBackend")]
+pub trait LockStrategy {
+    /// Releases the lock on drop
+    type Guard: LockGuard;
+
+    /// Should fail with `AlreadyExists` when someone else holds the lock
+    fn acquire(&self, xauth_path: &Path) -> io::Result<Self::Guard>;
+}
+
+pub trait LockGuard {
+    /// Whether the lock is still ours, i.e. nobody broke it thinking it was stale
+    fn still_held(&self) -> io::Result<bool>;
+}
+
+/// The `-c`/`-l` lockfile scheme used by libXau.
+/// Like libXau, breaks locks the default `StaleLockPolicy` considers abandoned
+#[derive(Debug, Clone, Copy)]
+pub struct DotLock {
+    heartbeat: Option<Duration>,
+    retry: RetryPolicy,
+    stale: Option<StaleLockPolicy>,
+}
+
+impl Default for DotLock {
+    fn default() -> Self {
+        Self {
+            heartbeat: None,
+            retry: RetryPolicy::default(),
+            stale: Some(StaleLockPolicy::default()),
+        }
+    }
+}
+
+impl DotLock {
+    /// Acquired locks are kept fresh with `Lock::start_heartbeat`
+    pub fn with_heartbeat(interval: Duration) -> Self {
+        Self {
+            heartbeat: Some(interval),
+            ..Self::default()
+        }
+    }
+
+    /// Retry while the lock is busy, instead of failing right away.
+    /// Stale locks fail without retrying, as waiting won't free them
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// When to break abandoned locks. With None they are left in place,
+    /// failing with `Error::LockStale` for the caller to decide
+    pub fn stale_locks(mut self, policy: Option<StaleLockPolicy>) -> Self {
+        self.stale = policy;
+        self
+    }
+}
+
+impl LockStrategy for DotLock {
+    type Guard = Lock;
+
+    fn acquire(&self, xauth_path: &Path) -> io::Result<Lock> {
+        let attempt = || {
+            Lock::aqquire_with(xauth_path, self.stale.as_ref()).map_err(|e| {
+                if e.kind() != io::ErrorKind::AlreadyExists {
+                    return e;
+                }
+
+                // Only for reporting, breaking it is up to the policy or the caller.
+                // Judged by the configured policy, or the default one if breaking is off
+                match Lock::state(xauth_path, &self.stale.unwrap_or_default()) {
+                    Ok(LockState::Stale { age }) => StaleLock::error(Some(age)),
+                    _ => e,
+                }
+            })
+        };
+
+        let mut lock = self.retry.run(attempt, |e| {
+            StaleLock::from_error(e).is_none() && RetryPolicy::is_transient(e)
+        })?;
+
+        if let Some(interval) = self.heartbeat {
+            lock.start_heartbeat(interval);
+        }
+
+        Ok(lock)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockState {
+    Free,
+    Held,
+    /// Held, but abandoned according to the `StaleLockPolicy` used
+    Stale {
+        age: Duration,
+    },
+}
+
+struct Heartbeat {
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl Heartbeat {
+    fn start(paths: [PathBuf; 2], interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                // Both paths are links to the same inode, unless one went missing
+                for path in &paths {
+                    if touch(path).is_ok() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        Self { stop, thread }
+    }
+
+    fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+fn touch(path: &Path) -> io::Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_modified(SystemTime::now())
+}
 
 pub struct Lock {
     creat_path: PathBuf,
     link_path: PathBuf,
+    id: LockId,
+    // False where hard links aren't supported, leaving only the creat file
+    linked: bool,
+    heartbeat: Option<Heartbeat>,
 }
 
 impl Lock {
+    /// Breaks the lock first if the default `StaleLockPolicy` considers it abandoned,
+    /// so a crashed holder can't wedge everyone after it
     pub fn aqquire(xauth_path: &Path) -> io::Result<Self> {
-        let filename = xauth_path.file_name().ok_or(io::Error::new(
-            io::ErrorKind::InvalidFilename,
-            "xauth_path does not end with a file",
-        ))?;
-        let filename = filename.to_str().unwrap(); // TODO: error
+        Self::aqquire_with(xauth_path, Some(&StaleLockPolicy::default()))
+    }
+
+    /// Like `aqquire`, waiting for the lock to be released according to `options`.
+    /// Still fails with `AlreadyExists` if it isn't
+    pub fn acquire_with(xauth_path: &Path, options: &LockOptions) -> io::Result<Self> {
+        options.retry.run(
+            || Self::aqquire_with(xauth_path, options.stale.as_ref()),
+            |e| e.kind() == io::ErrorKind::AlreadyExists,
+        )
+    }
+
+    /// A single attempt, with None if someone else holds the lock.
+    /// Like `aqquire`, stale locks are broken first, `aqquire_with` with None leaves them be
+    pub fn try_acquire(xauth_path: &Path) -> io::Result<Option<Self>> {
+        match Self::aqquire(xauth_path) {
+            Ok(lock) => Ok(Some(lock)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Like `aqquire`, breaking stale locks according to `stale`, or never with None
+    pub fn aqquire_with(xauth_path: &Path, stale: Option<&StaleLockPolicy>) -> io::Result<Self> {
+        if let Some(policy) = stale {
+            Self::break_stale(xauth_path, policy)?;
+        }
 
-        let creat_path = replace_filename(xauth_path.to_path_buf(), format!("{filename}-c"));
-        // TODO: for full parity need to handle case where filesystem doesnt support hard links
-        let link_path = replace_filename(xauth_path.to_path_buf(), format!("{filename}-l"));
+        let (creat_path, link_path) = lock_paths(xauth_path)?;
 
-        let lockfile = OpenOptions::new()
+        let mut lockfile = OpenOptions::new()
             .write(true)
             .create_new(true)
             .mode(0o600)
             .open(&creat_path)?;
 
-        drop(lockfile); // immediately close, as we don't need to interact with that file
+        // Checked by `still_held`, so a record that didn't make it means no lock
+        let record = holder_record();
+        if let Err(e) = lockfile.write_all(&record) {
+            let _ = remove_file(&creat_path);
+            return Err(e);
+        }
+        let metadata = lockfile.metadata()?;
+        drop(lockfile); // close, as we don't need to interact with that file anymore
 
-        hard_link(&creat_path, &link_path)?;
+        let linked = match hard_link(&creat_path, &link_path) {
+            Ok(()) => true,
+            Err(e) if links_unsupported(&e) => false,
+            Err(e) => {
+                // Don't leave a half made lock behind for the next attempt to trip over
+                let _ = remove_file(&creat_path);
+                return Err(e);
+            }
+        };
 
         Ok(Self {
             creat_path,
             link_path,
+            id: LockId {
+                inode: (metadata.dev(), metadata.ino()),
+                record,
+            },
+            linked,
+            heartbeat: None,
         })
     }
+
+    /// Refresh the lock files' mtime every `interval` from a background thread,
+    /// so long operations aren't mistaken for a stale lock by other processes.
+    /// `interval` should be well below the `max_age` other lock users apply
+    pub fn start_heartbeat(&mut self, interval: Duration) {
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.stop();
+        }
+
+        let paths = [self.creat_path.clone(), self.link_path.clone()];
+        self.heartbeat = Some(Heartbeat::start(paths, interval));
+    }
+
+    /// PID of the process holding the lock on `xauth_path`, if it recorded one.
+    /// It may be on another host sharing the file
+    pub fn holder_pid(xauth_path: &Path) -> io::Result<Option<u32>> {
+        Ok(Self::holder(xauth_path)?.map(|(pid, _)| pid))
+    }
+
+    fn holder(xauth_path: &Path) -> io::Result<Option<(u32, Hostname)>> {
+        let (creat_path, _) = lock_paths(xauth_path)?;
+
+        let record = match fs::read(creat_path) {
+            Ok(record) => record,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let (pid, host) = match record.iter().position(|&byte| byte == b' ') {
+            Some(space) => (&record[..space], record[space + 1..].to_vec()),
+            None => (&record[..], Vec::new()),
+        };
+
+        let pid = str::from_utf8(pid)
+            .ok()
+            .and_then(|pid| pid.trim().parse().ok());
+        Ok(pid.map(|pid| (pid, host)))
+    }
+
+    // Whether the holder is still running, if that can be told: its PID is only
+    // meaningful on its own host, and locks from elsewhere or without one are left to `max_age`
+    fn holder_alive(xauth_path: &Path) -> io::Result<Option<bool>> {
+        let Some((pid, host)) = Self::holder(xauth_path)? else {
+            return Ok(None);
+        };
+
+        match local_hostname() {
+            Ok(local) if !host.is_empty() && host == local => Ok(Some(process_alive(pid))),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn creat_path(&self) -> &Path {
+        &self.creat_path
+    }
+
+    /// Never created when `is_linked` is false
+    pub fn link_path(&self) -> &Path {
+        &self.link_path
+    }
+
+    /// False on filesystems without hard links, where the creat file alone is the lock
+    pub fn is_linked(&self) -> bool {
+        self.linked
+    }
+
+    fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        [&self.creat_path]
+            .into_iter()
+            .chain(self.linked.then_some(&self.link_path))
+    }
+
+    /// Inspects the lock on `xauth_path` without acquiring it
+    pub fn state(xauth_path: &Path, policy: &StaleLockPolicy) -> io::Result<LockState> {
+        Ok(Self::inspect(xauth_path, policy)?.0)
+    }
+
+    // Along with the lock files the state is about
+    fn inspect(
+        xauth_path: &Path,
+        policy: &StaleLockPolicy,
+    ) -> io::Result<(LockState, Option<LockId>)> {
+        let (creat_path, link_path) = lock_paths(xauth_path)?;
+
+        // The link may outlive the creat file if the holder died mid-release
+        let metadata = match fs::metadata(&creat_path).or_else(|_| fs::metadata(&link_path)) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((LockState::Free, None)),
+            Err(e) => return Err(e),
+        };
+        let id = match LockId::of(&creat_path)? {
+            Some(id) => Some(id),
+            None => LockId::of(&link_path)?,
+        };
+
+        let age = SystemTime::now()
+            .duration_since(metadata.modified()?)
+            .unwrap_or_default();
+
+        // Never linked: the holder died mid-acquire, is still in it, or hard links are unsupported.
+        // The last is common over NFS, where the holder may well be on another host
+        let unlinked = metadata.nlink() == 1 && !link_path.exists();
+        if unlinked && policy.check_holder && Self::holder_alive(xauth_path)? == Some(false) {
+            return Ok((LockState::Stale { age }, id));
+        }
+
+        if age <= policy.max_age {
+            return Ok((LockState::Held, id));
+        }
+
+        if policy.check_holder && Self::holder_alive(xauth_path)? == Some(true) {
+            return Ok((LockState::Held, id));
+        }
+
+        Ok((LockState::Stale { age }, id))
+    }
+
+    /// Removes the lock on `xauth_path` if `policy` considers it stale.
+    /// Returns whether the lock was removed
+    pub fn break_stale(xauth_path: &Path, policy: &StaleLockPolicy) -> io::Result<bool> {
+        let (LockState::Stale { .. }, Some(id)) = Self::inspect(xauth_path, policy)? else {
+            return Ok(false);
+        };
+
+        // Someone may have broken it and taken a fresh lock since, which has to stay
+        let (creat_path, link_path) = lock_paths(xauth_path)?;
+        let creat_removed = remove_if_same(&creat_path, &id)?;
+        let link_removed = remove_if_same(&link_path, &id)?;
+
+        Ok(creat_removed || link_removed)
+    }
+}
+
+impl LockGuard for Lock {
+    fn still_held(&self) -> io::Result<bool> {
+        for path in self.files() {
+            if LockId::of(path)?.as_ref() != Some(&self.id) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
 }
 
 impl Drop for Lock {
     fn drop(&mut self) {
-        let _ = remove_file(&self.creat_path);
-        let _ = remove_file(&self.link_path);
+        // Stop first, so the thread can't touch a lock someone else takes after removal
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.stop();
+        }
+
+        // Files that aren't ours anymore belong to whoever broke the lock and took it since
+        for path in self.files() {
+            let _ = remove_if_same(path, &self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("libxauth-lock-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("Xauthority")
+    }
+
+    fn backdate(path: &Path, age: Duration) {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    // Lock files as left behind by another process
+    fn leave_lock(xauth_path: &Path, record: &[u8], age: Duration) {
+        let (creat_path, link_path) = lock_paths(xauth_path).unwrap();
+        fs::write(&creat_path, record).unwrap();
+        hard_link(&creat_path, &link_path).unwrap();
+        backdate(&creat_path, age);
+    }
+
+    const NO_HOLDER_CHECK: StaleLockPolicy = StaleLockPolicy {
+        max_age: Duration::from_secs(60),
+        check_holder: false,
+    };
+
+    #[test]
+    fn lock_is_exclusive() {
+        let path = temp_path("exclusive");
+        let lock = Lock::aqquire_with(&path, None).unwrap();
+        assert!(lock.is_linked());
+        assert!(lock.creat_path().exists() && lock.link_path().exists());
+
+        let busy = Lock::aqquire_with(&path, None).err().unwrap();
+        assert_eq!(busy.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(Lock::holder_pid(&path).unwrap(), Some(process::id()));
+
+        let (creat_path, link_path) = (lock.creat_path().to_owned(), lock.link_path().to_owned());
+        drop(lock);
+        assert!(!creat_path.exists() && !link_path.exists());
+        assert!(Lock::try_acquire(&path).unwrap().is_some());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn old_lock_is_broken() {
+        let path = temp_path("old");
+        leave_lock(&path, b"1 elsewhere", Duration::from_secs(3600));
+
+        let state = Lock::state(&path, &NO_HOLDER_CHECK).unwrap();
+        assert!(matches!(state, LockState::Stale { age } if age >= Duration::from_secs(3600)));
+
+        assert!(Lock::break_stale(&path, &NO_HOLDER_CHECK).unwrap());
+        assert_eq!(
+            Lock::state(&path, &NO_HOLDER_CHECK).unwrap(),
+            LockState::Free
+        );
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn fresh_or_live_lock_is_kept() {
+        let path = temp_path("live");
+        let lock = Lock::aqquire_with(&path, None).unwrap();
+
+        assert!(!Lock::break_stale(&path, &NO_HOLDER_CHECK).unwrap());
+
+        // Old, but we are still running
+        backdate(lock.creat_path(), Duration::from_secs(3600));
+        let policy = StaleLockPolicy {
+            check_holder: true,
+            ..NO_HOLDER_CHECK
+        };
+        assert_eq!(Lock::state(&path, &policy).unwrap(), LockState::Held);
+        assert!(!Lock::break_stale(&path, &policy).unwrap());
+        assert!(lock.still_held().unwrap());
+
+        drop(lock);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn dead_holder_is_stale_before_max_age() {
+        let path = temp_path("dead");
+        let (creat_path, _) = lock_paths(&path).unwrap();
+
+        // Never linked, by a PID that can't exist
+        let mut record = b"4294967295 ".to_vec();
+        record.extend(local_hostname().unwrap());
+        fs::write(&creat_path, record).unwrap();
+
+        let policy = StaleLockPolicy::default();
+        assert!(matches!(
+            Lock::state(&path, &policy).unwrap(),
+            LockState::Stale { .. }
+        ));
+        assert!(Lock::aqquire(&path).is_ok());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn broken_lock_leaves_replacement_alone() {
+        let path = temp_path("replaced");
+        let old = Lock::aqquire_with(&path, None).unwrap();
+        backdate(old.creat_path(), Duration::from_secs(3600));
+
+        assert!(Lock::break_stale(&path, &NO_HOLDER_CHECK).unwrap());
+        // Taken by another process, which may well get the same inodes
+        leave_lock(&path, b"1 elsewhere", Duration::ZERO);
+        assert!(!old.still_held().unwrap());
+
+        drop(old);
+        let (creat_path, link_path) = lock_paths(&path).unwrap();
+        assert_eq!(fs::read(&creat_path).unwrap(), b"1 elsewhere");
+        assert!(link_path.exists());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn remove_if_same_keeps_other_files() {
+        let path = temp_path("remove-if-same");
+        fs::write(&path, b"1 host").unwrap();
+        let ours = LockId::of(&path).unwrap().unwrap();
+
+        // Replaced by someone else, possibly reusing the inode
+        fs::remove_file(&path).unwrap();
+        fs::write(&path, b"2 host").unwrap();
+        let theirs = LockId::of(&path).unwrap().unwrap();
+
+        assert!(!remove_if_same(&path, &ours).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), b"2 host");
+
+        assert!(remove_if_same(&path, &theirs).unwrap());
+        assert!(!remove_if_same(&path, &theirs).unwrap());
+
+        // Nothing left aside either
+        let dir = path.parent().unwrap();
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dot_lock_reports_stale_by_its_own_policy() {
+        let path = temp_path("dot-lock-policy");
+        // Stale by the default policy, which can't check a holder on another host
+        leave_lock(&path, b"1 elsewhere", Duration::from_secs(700));
+
+        let lenient = StaleLockPolicy {
+            max_age: Duration::from_secs(3600),
+            check_holder: false,
+        };
+        let busy = DotLock::default()
+            .stale_locks(Some(lenient))
+            .acquire(&path)
+            .err()
+            .unwrap();
+        assert_eq!(busy.kind(), io::ErrorKind::AlreadyExists);
+        assert!(StaleLock::from_error(&busy).is_none());
+
+        let reported = DotLock::default()
+            .stale_locks(None)
+            .acquire(&path)
+            .err()
+            .unwrap();
+        assert!(StaleLock::from_error(&reported).is_some());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
     }
 }
@@ -0,0 +1,211 @@
+use std::{
+    env, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+use crate::{
+    Authority, Entry, MIT_MAGIC_COOKIE_1, XDM_AUTHORIZATION_1,
+    display::DisplayName,
+    encoding::Family,
+    error::Result,
+    key::{EntryKey, local_hostname},
+};
+
+/// Protocol preference of `get_best`, most preferred first
+pub const DEFAULT_PROTOCOLS: &[&str] = &[MIT_MAGIC_COOKIE_1, XDM_AUTHORIZATION_1];
+
+/// Protocol preference of libxcb built with XDM-AUTHORIZATION-1 support, as most are
+pub const XCB_PROTOCOLS: &[&str] = &[XDM_AUTHORIZATION_1, MIT_MAGIC_COOKIE_1];
+
+/// How a client is connected to the server, for `match_for_connection`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionKind {
+    Unix,
+    /// The server's address, as `getpeername` reports it
+    Tcp(SocketAddr),
+}
+
+impl ConnectionKind {
+    /// The key libxcb looks up: unix sockets and loopback TCP use FamilyLocal
+    /// with the hostname, without Xlib's `XAUTHLOCALHOSTNAME` fallback
+    pub fn key(&self, display: u32) -> io::Result<EntryKey> {
+        let is_local = match self {
+            Self::Unix => true,
+            // Exactly 127.0.0.1, not the rest of 127.0.0.0/8
+            Self::Tcp(addr) => match addr.ip() {
+                IpAddr::V4(ip) => ip == Ipv4Addr::LOCALHOST,
+                IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                    Some(ip) => ip == Ipv4Addr::LOCALHOST,
+                    None => ip.is_loopback(),
+                },
+            },
+        };
+
+        match (self, is_local) {
+            (Self::Tcp(addr), false) => Ok(EntryKey::from_socket_addr(*addr, display)),
+            _ => Ok(EntryKey {
+                family: Family::Local,
+                address: local_hostname()?,
+                display_number: display.to_string(),
+                auth_name: MIT_MAGIC_COOKIE_1.to_string(),
+            }),
+        }
+    }
+}
+
+impl Entry {
+    /// Whether a client connecting to `key` would use this entry,
+    /// honoring FamilyWild and empty display numbers as wildcards.
+    /// The key's protocol is ignored, lookups take theirs from a preference list
+    pub fn matches(&self, key: &EntryKey) -> bool {
+        let address_matches = self.family == Family::Wild
+            || key.family == Family::Wild
+            || (self.family == key.family && self.address == key.address);
+
+        let display_matches =
+            self.display_number.is_empty() || self.display_number == key.display_number;
+
+        address_matches && display_matches
+    }
+}
+
+/// Result of `Authority::get_best_unexpired`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BestMatch<'a> {
+    Found(&'a Entry),
+    /// Only expired entries match. Worth regenerating the cookie rather than
+    /// connecting with a stale one
+    Expired(&'a Entry),
+    NotFound,
+}
+
+impl<'a> BestMatch<'a> {
+    /// The entry to use, if any isn't expired
+    pub fn found(self) -> Option<&'a Entry> {
+        match self {
+            Self::Found(entry) => Some(entry),
+            _ => None,
+        }
+    }
+}
+
+/// Tried in order for local-looking displays: names sshd and friends may have written
+fn local_keys(display: u32) -> io::Result<Vec<EntryKey>> {
+    let mut addresses = vec![local_hostname()?];
+
+    // Some distributions pin the name used for local entries,
+    // so cookies survive hostname changes
+    if let Some(name) = env::var_os("XAUTHLOCALHOSTNAME") {
+        addresses.push(name.into_encoded_bytes());
+    }
+
+    Ok(addresses
+        .into_iter()
+        .map(|address| EntryKey {
+            family: Family::Local,
+            address,
+            display_number: display.to_string(),
+            auth_name: MIT_MAGIC_COOKIE_1.to_string(),
+        })
+        .collect())
+}
+
+/// The keys clients look up for `display`, in order
+pub(crate) fn display_keys(display: &DisplayName) -> io::Result<Vec<EntryKey>> {
+    let is_loopback = matches!(display.host.as_str(), "localhost" | "127.0.0.1" | "::1");
+
+    let mut keys = match display.is_local() || is_loopback {
+        true => local_keys(display.display)?,
+        false => Vec::new(),
+    };
+
+    if !display.is_local() {
+        keys.push(EntryKey::from_display_target(display)?);
+    }
+
+    Ok(keys)
+}
+
+impl Authority {
+    /// The entry a client connecting to `key` should use, libXau style:
+    /// earlier protocols in `DEFAULT_PROTOCOLS` win, then earlier entries
+    pub fn get_best(&self, key: &EntryKey) -> Option<&Entry> {
+        self.get_best_with(key, DEFAULT_PROTOCOLS)
+    }
+
+    /// Like `get_best`, with `protocols` as the preference list, most preferred first.
+    /// Entries for protocols not in the list are never picked
+    pub fn get_best_with(&self, key: &EntryKey, protocols: &[&str]) -> Option<&Entry> {
+        protocols.iter().find_map(|protocol| {
+            self.iter()
+                .find(|entry| &*entry.auth_name == *protocol && entry.matches(key))
+        })
+    }
+
+    /// Like `get_best_with`, passing over entries `is_expired` rejects,
+    /// e.g. `Expiry::is_expired` from `AuthorityFile::expiry`
+    pub fn get_best_unexpired(
+        &self,
+        key: &EntryKey,
+        protocols: &[&str],
+        is_expired: impl Fn(&Entry) -> bool,
+    ) -> BestMatch<'_> {
+        let fresh = protocols.iter().find_map(|protocol| {
+            self.iter().find(|entry| {
+                &*entry.auth_name == *protocol && entry.matches(key) && !is_expired(entry)
+            })
+        });
+
+        match (fresh, self.get_best_with(key, protocols)) {
+            (Some(entry), _) => BestMatch::Found(entry),
+            (None, Some(entry)) => BestMatch::Expired(entry),
+            (None, None) => BestMatch::NotFound,
+        }
+    }
+
+    /// Entries a client of display `display` could use, whatever the host:
+    /// ones for that display, and ones with an empty display number matching any
+    pub fn entries_for_display(&self, display: u32) -> impl Iterator<Item = &Entry> {
+        self.iter().filter(move |entry| {
+            entry.display_number.is_empty() || entry.display() == Some(display)
+        })
+    }
+
+    /// Entry for a connection to `display`, matching the way libxcb does, see `ConnectionKind::key`
+    pub fn match_for_connection(
+        &self,
+        connection: ConnectionKind,
+        display: u32,
+    ) -> io::Result<Option<&Entry>> {
+        let key = connection.key(display)?;
+        Ok(self.get_best_with(&key, XCB_PROTOCOLS))
+    }
+
+    /// Parses `display`, e.g. `$DISPLAY`, and looks up its entry like `get_auth_for_display`.
+    /// The parsed name carries the screen to connect to, see `DisplayName::screen_or_default`
+    pub fn resolve_display(&self, display: &str) -> Result<(DisplayName, Option<&Entry>)> {
+        let display: DisplayName = display.parse()?;
+        let entry = self.get_auth_for_display(&display)?;
+
+        Ok((display, entry))
+    }
+
+    /// Entry for connecting to `display`, matching the way Xlib does.
+    ///
+    /// `localhost:N` displays, as created by SSH X11 forwarding, are looked up
+    /// as FamilyLocal with the real hostname first, since that's what sshd writes
+    pub fn get_auth_for_display(&self, display: &DisplayName) -> io::Result<Option<&Entry>> {
+        self.get_auth_for_display_with(display, DEFAULT_PROTOCOLS)
+    }
+
+    /// Like `get_auth_for_display`, with `protocols` as in `get_best_with`
+    pub fn get_auth_for_display_with(
+        &self,
+        display: &DisplayName,
+        protocols: &[&str],
+    ) -> io::Result<Option<&Entry>> {
+        Ok(display_keys(display)?
+            .iter()
+            .find_map(|key| self.get_best_with(key, protocols)))
+    }
+}
@@ -0,0 +1,58 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use crate::parse::ParseWarning;
+
+/// Something an `AuthorityFile` did, for counters and histograms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Metric {
+    /// The file was read and parsed
+    Read,
+    /// The file was rewritten or appended to
+    Write,
+    /// Time spent taking the lock, whether or not it succeeded
+    LockWait { wait: Duration, acquired: bool },
+    /// A lenient read recovered from this many problems
+    ParseWarnings(usize),
+    /// `prune_older_than` removed this many entries
+    Pruned(usize),
+    /// A file was opened with `OpenMode::IgnoreLock`, unprotected from other writers
+    LockIgnored,
+}
+
+pub trait MetricsObserver: Send + Sync {
+    /// Called inline, keep it cheap
+    fn record(&self, metric: Metric);
+}
+
+static OBSERVER: RwLock<Option<Arc<dyn MetricsObserver>>> = RwLock::new(None);
+
+/// Process-wide, like `set_secret_access_observer`
+pub fn set_metrics_observer(observer: Option<Arc<dyn MetricsObserver>>) {
+    *OBSERVER.write().unwrap_or_else(|e| e.into_inner()) = observer;
+}
+
+pub(crate) fn record(metric: Metric) {
+    let observer = OBSERVER.read().unwrap_or_else(|e| e.into_inner());
+
+    if let Some(observer) = observer.as_ref() {
+        observer.record(metric);
+    }
+}
+
+/// Records the outcome of a read, passing it through
+pub(crate) fn read<T, E>(
+    result: Result<(T, Vec<ParseWarning>), E>,
+) -> Result<(T, Vec<ParseWarning>), E> {
+    if let Ok((_, warnings)) = &result {
+        record(Metric::Read);
+        if !warnings.is_empty() {
+            record(Metric::ParseWarnings(warnings.len()));
+        }
+    }
+
+    result
+}
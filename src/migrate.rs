@@ -0,0 +1,88 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Authority,
+    display::DisplayName,
+    encoding::Entry,
+    env::{display_from_env, env_path},
+    error::{Error, Result},
+    file::{Duplicates, transfer},
+    lookup::display_keys,
+    multilock::MultiLock,
+};
+
+/// Result of `migrate_to_runtime_dir`
+#[derive(Debug, Clone)]
+pub struct Migration {
+    /// The authority to use from now on
+    pub path: PathBuf,
+    pub moved: Vec<Entry>,
+    /// Wildcard entries, which serve other displays too and so are in both files
+    pub copied: Vec<Entry>,
+}
+
+impl Migration {
+    /// The new `XAUTHORITY`, for the session environment
+    pub fn env(&self) -> (&'static str, String) {
+        ("XAUTHORITY", self.path.display().to_string())
+    }
+}
+
+/// Moves the entries for `$DISPLAY` from `~/.Xauthority` to `$XDG_RUNTIME_DIR/Xauthority`
+pub fn migrate_to_runtime_dir() -> Result<Migration> {
+    let display = display_from_env()?;
+
+    migrate_between(
+        &env_path("HOME")?.join(".Xauthority"),
+        &env_path("XDG_RUNTIME_DIR")?.join("Xauthority"),
+        &display,
+    )
+}
+
+/// Like `migrate_to_runtime_dir`, with explicit paths.
+///
+/// Entries for exactly `display` move to `runtime`, created if missing. Wildcard entries
+/// clients would consider for it are copied, as clients of other displays may rely on them.
+/// The rest stays in `home`, which is kept even if empty, for tools that expect it to exist.
+/// Nothing else is written there
+pub fn migrate_between(home: &Path, runtime: &Path, display: &DisplayName) -> Result<Migration> {
+    let keys = display_keys(display)?;
+
+    let mut locks = MultiLock::acquire(&[home, runtime])?;
+    let mut src = match locks.open_existing(home) {
+        Ok(file) => file,
+        // Nothing to migrate
+        Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(Migration {
+                path: runtime.to_path_buf(),
+                moved: Vec::new(),
+                copied: Vec::new(),
+            });
+        }
+        Err(e) => return Err(e),
+    };
+    let mut dst = locks.open(runtime)?;
+
+    let moved = transfer(&mut src, &mut dst, |entry| {
+        keys.iter().any(|key| entry.is_for(key))
+    })?;
+
+    let copied: Vec<Entry> = src
+        .get()?
+        .into_iter()
+        .filter(|entry| keys.iter().any(|key| entry.matches(key)))
+        .collect();
+
+    if !copied.is_empty() {
+        dst.append_with(Authority::new(Some(copied.clone())), Duplicates::Replace)?;
+    }
+
+    Ok(Migration {
+        path: runtime.to_path_buf(),
+        moved,
+        copied,
+    })
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::VecDeque,
+    io,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::lock::{LockGuard, LockStrategy, StaleLock};
+
+/// What the next call to `MockLock::acquire` does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockOutcome {
+    Acquire,
+    /// Another process holds the lock
+    Busy,
+    /// Lockfiles exist, but were left behind by a dead holder
+    Stale,
+    /// The filesystem doesn't support hard links. Acquires like `Lock` does there,
+    /// with only the creat file, see `MockLockGuard::is_linked`
+    HardLinkUnsupported,
+}
+
+impl MockOutcome {
+    fn into_error(self) -> Option<io::Error> {
+        match self {
+            Self::Acquire | Self::HardLinkUnsupported => None,
+            Self::Busy => Some(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "mock lock is held",
+            )),
+            Self::Stale => Some(StaleLock::error(None)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    script: VecDeque<MockOutcome>,
+    stale: bool,
+    held: bool,
+    attempts: usize,
+    // Bumped whenever the lock is broken, invalidating prior guards
+    generation: usize,
+}
+
+/// A scriptable `LockStrategy` for testing retry/backoff handling.
+///
+/// Outcomes queued with `then` are consumed one per `acquire` call,
+/// after which the lock behaves like a real one: it succeeds unless
+/// a guard from this `MockLock` (or a clone of it) is still alive
+#[derive(Debug, Clone, Default)]
+pub struct MockLock {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockLock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn then(self, outcome: MockOutcome) -> Self {
+        self.state().script.push_back(outcome);
+        self
+    }
+
+    /// Fail the next `attempts` acquisitions as if another process held the lock
+    pub fn contended(self, attempts: usize) -> Self {
+        (0..attempts).fold(self, |lock, _| lock.then(MockOutcome::Busy))
+    }
+
+    /// Once the script runs out, keep reporting a stale lock until `clear_stale`
+    pub fn stale(self) -> Self {
+        self.state().stale = true;
+        self
+    }
+
+    /// Simulates a successful stale lock removal
+    pub fn clear_stale(&self) {
+        self.state().stale = false;
+    }
+
+    pub fn attempts(&self) -> usize {
+        self.state().attempts
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.state().held
+    }
+
+    /// Simulates another process breaking the current lock as stale
+    pub fn break_lock(&self) {
+        let mut state = self.state();
+        state.held = false;
+        state.generation += 1;
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl LockStrategy for MockLock {
+    type Guard = MockLockGuard;
+
+    fn acquire(&self, _xauth_path: &Path) -> io::Result<MockLockGuard> {
+        let mut state = self.state();
+        state.attempts += 1;
+
+        let outcome = match state.script.pop_front() {
+            Some(outcome) => outcome,
+            None if state.stale => MockOutcome::Stale,
+            None if state.held => MockOutcome::Busy,
+            None => MockOutcome::Acquire,
+        };
+
+        if let Some(e) = outcome.into_error() {
+            return Err(e);
+        }
+
+        state.held = true;
+
+        Ok(MockLockGuard {
+            state: self.state.clone(),
+            generation: state.generation,
+            linked: outcome != MockOutcome::HardLinkUnsupported,
+        })
+    }
+}
+
+pub struct MockLockGuard {
+    state: Arc<Mutex<MockState>>,
+    generation: usize,
+    linked: bool,
+}
+
+impl MockLockGuard {
+    /// Like `Lock::is_linked`
+    pub fn is_linked(&self) -> bool {
+        self.linked
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl LockGuard for MockLockGuard {
+    fn still_held(&self) -> io::Result<bool> {
+        Ok(self.state().generation == self.generation)
+    }
+}
+
+impl Drop for MockLockGuard {
+    fn drop(&mut self) {
+        let mut state = self.state();
+
+        // A broken lock may have been taken by someone else since
+        if state.generation == self.generation {
+            state.held = false;
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::Result,
+    file::{AuthorityFile, lock_in},
+    lock::LockGuard,
+    storage::{Fs, Storage},
+};
+
+/// The same file under any of its names, for ordering and deduplication.
+/// The file itself may not exist yet, its directory should
+fn canonical(path: &Path) -> PathBuf {
+    let resolved = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = match parent.as_os_str().is_empty() {
+                true => Path::new("."),
+                false => parent,
+            };
+            fs::canonicalize(parent).map(|parent| parent.join(name))
+        }
+        _ => fs::canonicalize(path),
+    };
+
+    resolved.unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Locks on several authority files, taken in a canonical order so that two processes
+/// locking the same files can't each end up holding one the other waits for.
+///
+/// If any lock can't be taken, those already taken are released before returning the error
+pub struct MultiLock<S: Storage = Fs> {
+    // Sorted by canonical path
+    locks: Vec<(PathBuf, Option<S::Lock>)>,
+}
+
+impl MultiLock {
+    pub fn acquire(paths: &[&Path]) -> Result<Self> {
+        Self::acquire_in(&Fs::default(), paths)
+    }
+
+    /// Opens a locked file, creating it if missing, see `open_in`
+    pub fn open(&mut self, path: &Path) -> Result<AuthorityFile> {
+        self.open_in(&Fs::default(), path)
+    }
+
+    pub fn open_existing(&mut self, path: &Path) -> Result<AuthorityFile> {
+        self.open_existing_in(&Fs::default(), path)
+    }
+}
+
+impl<S: Storage> MultiLock<S> {
+    pub fn acquire_in(storage: &S, paths: &[&Path]) -> Result<Self> {
+        let mut targets: Vec<(PathBuf, &Path)> =
+            paths.iter().map(|path| (canonical(path), *path)).collect();
+        targets.sort_by(|(a, _), (b, _)| a.cmp(b));
+        // Locking the same file twice would fail against ourselves
+        targets.dedup_by(|(a, _), (b, _)| a == b);
+
+        let mut locks = Vec::with_capacity(targets.len());
+        for (canonical, path) in targets {
+            // On failure, dropping `locks` releases what we have so far
+            locks.push((canonical, Some(lock_in(storage, path)?)));
+        }
+
+        Ok(Self { locks })
+    }
+
+    /// Opens `path`, one of the locked files, handing its lock over to the returned file.
+    /// A missing file is created with mode 0600
+    pub fn open_in(&mut self, storage: &S, path: &Path) -> Result<AuthorityFile<S>> {
+        let lock = self.take(path)?;
+        AuthorityFile::open_locked_in(storage, path, lock, true)
+    }
+
+    /// Like `open_in`, but fails with `NotFound` if the file doesn't exist.
+    /// The lock is released then
+    pub fn open_existing_in(&mut self, storage: &S, path: &Path) -> Result<AuthorityFile<S>> {
+        let lock = self.take(path)?;
+        AuthorityFile::open_locked_in(storage, path, lock, false)
+    }
+
+    fn take(&mut self, path: &Path) -> Result<S::Lock> {
+        let canonical = canonical(path);
+
+        let lock = self
+            .locks
+            .iter_mut()
+            .find(|(locked, _)| *locked == canonical)
+            .and_then(|(_, lock)| lock.take())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not locked here, or already opened", path.display()),
+                )
+            })?;
+
+        Ok(lock)
+    }
+
+    /// Whether every lock not handed over yet is still ours
+    pub fn still_held(&self) -> Result<bool> {
+        for lock in self.locks.iter().filter_map(|(_, lock)| lock.as_ref()) {
+            if !lock.still_held()? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    os::{fd::AsRawFd, unix::fs::MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    lock::{LockGuard, LockStrategy},
+    retry::RetryPolicy,
+};
+
+unsafe extern "C" {
+    fn fcntl(fd: i32, cmd: i32, ...) -> i32;
+}
+
+#[repr(C)]
+struct Flock {
+    l_type: i16,
+    l_whence: i16,
+    l_start: i64,
+    l_len: i64,
+    l_pid: i32,
+}
+
+#[cfg(target_os = "linux")]
+const F_OFD_SETLK: Option<i32> = Some(37);
+#[cfg(not(target_os = "linux"))]
+const F_OFD_SETLK: Option<i32> = None;
+
+const F_WRLCK: i16 = 1;
+const SEEK_SET: i16 = 0;
+
+/// Kernel enforced `fcntl` open file description locks on the authority itself.
+/// They go away with the process holding them, so a crash can't leave a stale lock behind.
+///
+/// libXau and xauth don't see these, use them where every writer goes through this crate.
+/// Needs the file to exist before locking, and Linux 3.15 or later
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OfdLock {
+    retry: RetryPolicy,
+}
+
+impl OfdLock {
+    /// Retry while the lock is busy, instead of failing right away
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    fn try_lock(xauth_path: &Path) -> io::Result<OfdLockGuard> {
+        let command = F_OFD_SETLK.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "open file description locks are Linux only",
+            )
+        })?;
+
+        let file = OpenOptions::new().read(true).write(true).open(xauth_path)?;
+
+        // The whole file, however long it gets
+        let flock = Flock {
+            l_type: F_WRLCK,
+            l_whence: SEEK_SET,
+            l_start: 0,
+            l_len: 0,
+            l_pid: 0,
+        };
+
+        if unsafe { fcntl(file.as_raw_fd(), command, &flock as *const Flock) } != 0 {
+            let e = io::Error::last_os_error();
+            return Err(match e.kind() {
+                // Reported like a busy dot lock
+                io::ErrorKind::WouldBlock | io::ErrorKind::PermissionDenied => {
+                    io::Error::new(io::ErrorKind::AlreadyExists, e)
+                }
+                _ => e,
+            });
+        }
+
+        Ok(OfdLockGuard {
+            file,
+            path: xauth_path.to_path_buf(),
+        })
+    }
+}
+
+impl LockStrategy for OfdLock {
+    type Guard = OfdLockGuard;
+
+    fn acquire(&self, xauth_path: &Path) -> io::Result<OfdLockGuard> {
+        self.retry
+            .run(|| Self::try_lock(xauth_path), RetryPolicy::is_transient)
+    }
+}
+
+/// Releases the lock when dropped, or when the process dies
+pub struct OfdLockGuard {
+    file: File,
+    path: PathBuf,
+}
+
+impl LockGuard for OfdLockGuard {
+    /// Nobody can break these, but the path may have been replaced by another file
+    fn still_held(&self) -> io::Result<bool> {
+        let locked = self.file.metadata()?;
+
+        match fs::metadata(&self.path) {
+            Ok(current) => Ok((current.dev(), current.ino()) == (locked.dev(), locked.ino())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    fmt,
+    io::{self, Read},
+    ops::Range,
+};
+
+use crate::{
+    Authority, Entry,
+    encoding::{Family, Interner, RawEntry, expected_address_len},
+    family_codes::*,
+};
+
+/// What to do with display numbers and protocol names that aren't valid UTF-8
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Utf8Policy {
+    /// Skip the entry
+    #[default]
+    Strict,
+    /// Replace invalid sequences with U+FFFD
+    Lossy,
+    /// Read every byte as the char with the same value (Latin-1),
+    /// so `field.chars().map(|c| c as u8)` gives back the bytes on disk.
+    ///
+    /// Lossy like `Lossy` when written back: the chars are encoded as UTF-8,
+    /// so bytes above 0x7f change on disk
+    Latin1,
+}
+
+enum Decoded {
+    Valid(String),
+    Recovered(String),
+    Rejected,
+}
+
+impl Utf8Policy {
+    fn decode(self, bytes: Vec<u8>) -> Decoded {
+        let bytes = match String::from_utf8(bytes) {
+            Ok(string) => return Decoded::Valid(string),
+            Err(e) => e.into_bytes(),
+        };
+
+        match self {
+            Self::Strict => Decoded::Rejected,
+            Self::Lossy => Decoded::Recovered(String::from_utf8_lossy(&bytes).into_owned()),
+            Self::Latin1 => Decoded::Recovered(bytes.into_iter().map(char::from).collect()),
+        }
+    }
+}
+
+/// Entries with their byte ranges in the input, and what went wrong along the way
+pub type Parsed = (Vec<(Range<u64>, Entry)>, Vec<ParseWarning>);
+
+/// Options for lenient parsing, in the style of `std::fs::OpenOptions`
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    utf8: Utf8Policy,
+    validate_addresses: bool,
+    resync: bool,
+    families: Option<Vec<Family>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WarningReason {
+    /// Handled according to the `Utf8Policy`
+    InvalidUtf8 { field: &'static str },
+    /// The file ends mid-entry. Nothing after `offset` could be read
+    Truncated,
+    /// The address length doesn't fit the family, most likely a corrupted file.
+    /// The entry was skipped
+    InvalidAddress { expected: usize, found: usize },
+    /// With `ParseOptions::resync`: `len` bytes didn't form a plausible entry
+    /// and were skipped, parsing resumed after them
+    Skipped { len: u64 },
+}
+
+/// A problem lenient parsing recovered from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWarning {
+    /// Position of the entry among all entries in the file, including skipped ones
+    pub index: usize,
+    /// Where the entry starts, in bytes
+    pub offset: u64,
+    pub reason: WarningReason,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entry {} at offset {}: ", self.index, self.offset)?;
+
+        match self.reason {
+            WarningReason::InvalidUtf8 { field } => write!(f, "{field} is not valid UTF-8"),
+            WarningReason::Truncated => f.write_str("truncated"),
+            WarningReason::InvalidAddress { expected, found } => {
+                write!(f, "address is {found} bytes, expected {expected}")
+            }
+            WarningReason::Skipped { len } => write!(f, "skipped {len} unreadable bytes"),
+        }
+    }
+}
+
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+impl Authority {
+    /// Reads as much as possible, skipping entries that can't be decoded
+    /// instead of failing. I/O errors are still returned as such
+    pub fn read_lenient<R: Read>(reader: &mut R) -> io::Result<(Self, Vec<ParseWarning>)> {
+        ParseOptions::new().read(reader)
+    }
+}
+
+impl ParseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn utf8(mut self, policy: Utf8Policy) -> Self {
+        self.utf8 = policy;
+        self
+    }
+
+    /// Skip entries whose address length doesn't fit their family, see `Entry::validate`
+    pub fn validate_addresses(mut self, enabled: bool) -> Self {
+        self.validate_addresses = enabled;
+        self
+    }
+
+    /// After an entry that can't be read, scan forward for the next plausible one
+    /// instead of giving up on the rest of the file. Reads the whole input upfront.
+    ///
+    /// Stricter about what counts as an entry: unknown families, non-numeric display numbers
+    /// and non-ASCII protocol names are taken for corruption
+    pub fn resync(mut self, enabled: bool) -> Self {
+        self.resync = enabled;
+        self
+    }
+
+    /// Only keep entries of these families. Others are skipped without decoding them
+    /// or producing warnings, e.g. to read just the Local and Wild entries of a site-wide file
+    pub fn families(mut self, families: &[Family]) -> Self {
+        self.families = Some(families.to_vec());
+        self
+    }
+
+    fn wanted(&self, family: u16) -> bool {
+        self.families
+            .as_ref()
+            .is_none_or(|families| families.contains(&Family::from_code(family)))
+    }
+
+    /// See `Authority::read_lenient`
+    pub fn read<R: Read>(&self, reader: &mut R) -> io::Result<(Authority, Vec<ParseWarning>)> {
+        let (entries, warnings) = self.read_with_offsets(reader)?;
+        let entries = entries.into_iter().map(|(_, entry)| entry).collect();
+
+        Ok((Authority::new(Some(entries)), warnings))
+    }
+
+    /// Like `read`, along with the byte range each entry occupies in the input
+    pub fn read_with_offsets<R: Read>(&self, reader: &mut R) -> io::Result<Parsed> {
+        if self.resync {
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf)?;
+            return Ok(self.read_resync(&buf));
+        }
+
+        let mut reader = CountingReader {
+            inner: reader,
+            count: 0,
+        };
+
+        let mut entries = Vec::new();
+        let mut warnings = Vec::new();
+        let mut interner = Interner::new();
+
+        for index in 0.. {
+            let offset = reader.count;
+
+            let raw = match RawEntry::read_family(&mut reader) {
+                Ok(Some(family)) if self.wanted(family) => RawEntry::read_rest(&mut reader, family),
+                Ok(Some(_)) => match RawEntry::skip_rest(&mut reader) {
+                    Ok(()) => continue,
+                    Err(e) => Err(e),
+                },
+                Ok(None) => break,
+                Err(e) => Err(e),
+            };
+
+            let raw = match raw {
+                Ok(raw) => raw,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    warnings.push(ParseWarning {
+                        index,
+                        offset,
+                        reason: WarningReason::Truncated,
+                    });
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let range = offset..reader.count;
+            let entry = self.decode(raw, index, offset, &mut interner, &mut warnings);
+            entries.extend(entry.map(|entry| (range, entry)));
+        }
+
+        Ok((entries, warnings))
+    }
+
+    fn read_resync(&self, buf: &[u8]) -> Parsed {
+        let mut entries = Vec::new();
+        let mut warnings = Vec::new();
+        let mut interner = Interner::new();
+
+        let mut position = 0;
+        for index in 0.. {
+            if position == buf.len() {
+                break;
+            }
+
+            let offset = position as u64;
+
+            if let Some((raw, len)) = plausible_entry(&buf[position..]) {
+                let range = offset..offset + len as u64;
+                let entry = self.decode(raw, index, offset, &mut interner, &mut warnings);
+                entries.extend(entry.map(|entry| (range, entry)));
+                position += len;
+                continue;
+            }
+
+            // One warning for the whole unreadable stretch
+            let skipped = (position + 1..buf.len())
+                .find(|start| plausible_len(&buf[*start..]).is_some())
+                .unwrap_or(buf.len())
+                - position;
+
+            warnings.push(ParseWarning {
+                index,
+                offset,
+                reason: WarningReason::Skipped {
+                    len: skipped as u64,
+                },
+            });
+            position += skipped;
+        }
+
+        (entries, warnings)
+    }
+
+    fn decode(
+        &self,
+        raw: RawEntry,
+        index: usize,
+        offset: u64,
+        interner: &mut Interner,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Option<Entry> {
+        if !self.wanted(raw.family) {
+            return None;
+        }
+
+        let mut warn = |reason| {
+            warnings.push(ParseWarning {
+                index,
+                offset,
+                reason,
+            })
+        };
+
+        if self.validate_addresses
+            && let Some(expected) = expected_address_len(raw.family)
+            && expected != raw.address.len()
+        {
+            warn(WarningReason::InvalidAddress {
+                expected,
+                found: raw.address.len(),
+            });
+            return None;
+        }
+
+        let mut decode = |field, bytes| match self.utf8.decode(bytes) {
+            Decoded::Valid(string) => Some(string),
+            Decoded::Recovered(string) => {
+                warn(WarningReason::InvalidUtf8 { field });
+                Some(string)
+            }
+            Decoded::Rejected => {
+                warn(WarningReason::InvalidUtf8 { field });
+                None
+            }
+        };
+
+        let display_number = decode("display_number", raw.display_number);
+        let auth_name = decode("auth_name", raw.auth_name)?;
+        let display_number = display_number?;
+
+        Some(Entry::from_parts(
+            Family::from_code(raw.family),
+            raw.address,
+            display_number,
+            interner.intern(&auth_name),
+            raw.auth_data,
+        ))
+    }
+}
+
+const KNOWN_FAMILIES: &[u16] = &[
+    FAMILY_INTERNET,
+    FAMILY_DECNET,
+    FAMILY_CHAOS,
+    FAMILY_SERVER_INTERPRETED,
+    FAMILY_INTERNET6,
+    FAMILY_LOCAL_HOST,
+    FAMILY_KRB5_PRINCIPAL,
+    FAMILY_NETNAME,
+    FAMILY_LOCAL,
+    FAMILY_WILD,
+];
+
+/// An entry starting at `bytes`, with its encoded length, if it looks like a real one
+pub(crate) fn plausible_entry(bytes: &[u8]) -> Option<(RawEntry, usize)> {
+    let len = plausible_len(bytes)?;
+    let raw = RawEntry::read_from(&mut &bytes[..len]).ok()??;
+
+    Some((raw, len))
+}
+
+/// Like `plausible_entry`, checked in place. Resyncing tries every offset of a
+/// damaged file, so nothing is allocated for the ones that aren't an entry
+fn plausible_len(bytes: &[u8]) -> Option<usize> {
+    let u16_at = |at: usize| Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?));
+
+    let family = u16_at(0)?;
+    let mut position = 2;
+    let mut field = || {
+        let len = u16_at(position)? as usize;
+        let field = bytes.get(position + 2..position + 2 + len)?;
+        position += 2 + len;
+        Some(field)
+    };
+
+    let address = field()?;
+    let display_number = field()?;
+    let auth_name = field()?;
+    field()?;
+
+    let plausible = KNOWN_FAMILIES.contains(&family)
+        && expected_address_len(family).is_none_or(|len| len == address.len())
+        && display_number.iter().all(u8::is_ascii_digit)
+        && !auth_name.is_empty()
+        && auth_name.iter().all(u8::is_ascii_graphic);
+
+    plausible.then_some(position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MIT_MAGIC_COOKIE_1;
+
+    fn encoded(display: u32) -> Vec<u8> {
+        let entry = Entry::builder()
+            .family(Family::Local)
+            .address(*b"host")
+            .display(display)
+            .protocol(MIT_MAGIC_COOKIE_1)
+            .data([display as u8; 16])
+            .build()
+            .unwrap();
+
+        let mut bytes = Vec::new();
+        entry.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn displays(authority: &Authority) -> Vec<u32> {
+        authority
+            .iter()
+            .map(|entry| entry.display().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn resync_skips_garbage_between_entries() {
+        let first = encoded(1);
+        let mut bytes = first.clone();
+        bytes.extend(b"\xff\xff\xff");
+        bytes.extend(encoded(2));
+
+        let (authority, warnings) = ParseOptions::new()
+            .resync(true)
+            .read(&mut bytes.as_slice())
+            .unwrap();
+
+        assert_eq!(displays(&authority), [1, 2]);
+        assert_eq!(
+            warnings,
+            [ParseWarning {
+                index: 1,
+                offset: first.len() as u64,
+                reason: WarningReason::Skipped { len: 3 },
+            }]
+        );
+    }
+
+    #[test]
+    fn without_resync_garbage_ends_the_file() {
+        let mut bytes = encoded(1);
+        bytes.extend(b"\xff\xff\xff");
+        bytes.extend(encoded(2));
+
+        let (authority, warnings) = ParseOptions::new().read(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(displays(&authority), [1]);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].reason, WarningReason::Truncated);
+    }
+
+    #[test]
+    fn resync_skips_truncated_tail() {
+        let mut bytes = encoded(1);
+        bytes.extend(&encoded(2)[..5]);
+
+        let (authority, warnings) = ParseOptions::new()
+            .resync(true)
+            .read(&mut bytes.as_slice())
+            .unwrap();
+
+        assert_eq!(displays(&authority), [1]);
+        assert_eq!(warnings[0].reason, WarningReason::Skipped { len: 5 });
+    }
+}
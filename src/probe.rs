@@ -0,0 +1,108 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{
+    Authority,
+    audit::{self, SecretAccess},
+    display::{DisplayName, connect_local},
+    encoding::Entry,
+    key::resolvable_host,
+    lookup::display_keys,
+    proxy::SetupRequest,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How a running server answered a connection setup, see `Authority::probe_server`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerVerdict {
+    Accepted,
+    /// With the server's reason, e.g. "Invalid MIT-MAGIC-COOKIE-1 key"
+    Refused(String),
+    /// The server asked for further authentication, which probing doesn't do
+    Authenticate,
+}
+
+trait Connection: Read + Write {}
+
+impl<T: Read + Write> Connection for T {}
+
+fn connect(display: &DisplayName) -> io::Result<Box<dyn Connection>> {
+    if display.is_local() {
+        let stream = connect_local(display.display)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        return Ok(Box::new(stream));
+    }
+
+    let port = u16::try_from(6000 + display.display as u64)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "display number too large"))?;
+    // Each address in turn, like `TcpStream::connect`, but without hanging on unreachable hosts
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "host has no addresses");
+    for addr in (resolvable_host(&display.host)?, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                return Ok(Box::new(stream));
+            }
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+fn probe(display: &DisplayName, entry: &Entry) -> io::Result<ServerVerdict> {
+    let setup = SetupRequest {
+        byte_order: b'l',
+        major_version: 11,
+        minor_version: 0,
+        auth_name: entry.auth_name.as_bytes().to_vec(),
+        auth_data: entry.auth_data().to_vec(),
+    };
+
+    let mut connection = connect(display)?;
+    connection.write_all(&setup.encode()?)?;
+
+    // status, reason length, protocol version, then the length of the rest in 4 byte units
+    let mut header = [0u8; 8];
+    connection.read_exact(&mut header)?;
+
+    match header[0] {
+        0 => {
+            let mut reason = vec![0u8; header[1] as usize];
+            connection.read_exact(&mut reason)?;
+
+            let reason = String::from_utf8_lossy(&reason).trim_end().to_string();
+            Ok(ServerVerdict::Refused(reason))
+        }
+        1 => Ok(ServerVerdict::Accepted),
+        2 => Ok(ServerVerdict::Authenticate),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an X11 connection setup reply",
+        )),
+    }
+}
+
+impl Authority {
+    /// Tries each entry a client of `display` could use against its running server,
+    /// one connection per entry, telling which ones the server actually accepts.
+    /// For debugging clients being refused despite an entry being there.
+    ///
+    /// Done with plain connection setups, so no X binding is needed.
+    /// The credentials are sent to the server, so entries for other hosts never are
+    pub fn probe_server(&self, display: &DisplayName) -> io::Result<Vec<(&Entry, ServerVerdict)>> {
+        let keys = display_keys(display)?;
+
+        self.iter()
+            .filter(|entry| keys.iter().any(|key| entry.matches(key)))
+            .map(|entry| {
+                audit::check(SecretAccess::Serialize, &entry.auth_name)?;
+                Ok((entry, probe(display, entry)?))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,67 @@
+use std::io::{self, Read};
+
+use crate::{Authority, compat::CompatOptions, display::DisplayName};
+
+const MAGIC: &[u8] = b"XAUTHPRV";
+const VERSION: u8 = 1;
+
+/// Entries for provisioning another machine's authority, along with the display they're for.
+///
+/// Holds cookies in the clear, only send it over an authenticated and encrypted channel
+pub struct ProvisioningPacket {
+    /// The display on the receiving side, e.g. to export as `$DISPLAY`
+    pub display: DisplayName,
+    pub authority: Authority,
+}
+
+impl ProvisioningPacket {
+    pub fn new(display: DisplayName, authority: Authority) -> Self {
+        Self { display, authority }
+    }
+
+    pub fn pack(&self) -> io::Result<Vec<u8>> {
+        let display = self.display.to_string();
+        let display_len = u16::try_from(display.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "display name too long"))?;
+
+        let mut buf = MAGIC.to_vec();
+        buf.push(VERSION);
+        buf.extend_from_slice(&display_len.to_be_bytes());
+        buf.extend_from_slice(display.as_bytes());
+
+        // Entries are in the authority file format, which delimits itself
+        self.authority
+            .write_with(&mut buf, &CompatOptions::default())?;
+
+        Ok(buf)
+    }
+
+    pub fn unpack(mut bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |what| io::Error::new(io::ErrorKind::InvalidData, what);
+
+        let mut header = [0u8; 11];
+        bytes.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(invalid("not a provisioning packet"));
+        }
+        if header[8] != VERSION {
+            return Err(invalid("unsupported provisioning packet version"));
+        }
+
+        let display_len = u16::from_be_bytes([header[9], header[10]]) as usize;
+        if display_len > bytes.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (display, mut entries) = bytes.split_at(display_len);
+
+        let display = str::from_utf8(display)
+            .ok()
+            .and_then(|display| display.parse().ok())
+            .ok_or_else(|| invalid("invalid display name"))?;
+
+        Ok(Self {
+            display,
+            authority: Authority::read_from(&mut entries)?,
+        })
+    }
+}
@@ -0,0 +1,173 @@
+use std::{fmt, io};
+
+use crate::{
+    Authority,
+    audit::{self, Redacted, SecretAccess},
+    display::DisplayName,
+};
+
+/// The credentials part of an X11 connection setup request, as sent by clients
+#[derive(Clone, PartialEq, Eq)]
+pub struct SetupRequest {
+    /// `b'B'` for big endian, `b'l'` for little endian
+    pub byte_order: u8,
+    pub major_version: u16,
+    pub minor_version: u16,
+    pub auth_name: Vec<u8>,
+    pub auth_data: Vec<u8>,
+}
+
+impl fmt::Debug for SetupRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SetupRequest")
+            .field("byte_order", &self.byte_order)
+            .field("major_version", &self.major_version)
+            .field("minor_version", &self.minor_version)
+            .field("auth_name", &String::from_utf8_lossy(&self.auth_name))
+            .field("auth_data", &Redacted(&self.auth_data))
+            .finish()
+    }
+}
+
+fn padded(len: usize) -> usize {
+    len.next_multiple_of(4)
+}
+
+impl SetupRequest {
+    const HEADER_LEN: usize = 12;
+
+    /// The request at the start of `buf` and its length,
+    /// or None if more bytes are needed
+    pub fn parse(buf: &[u8]) -> io::Result<Option<(Self, usize)>> {
+        let Some(header) = buf.get(..Self::HEADER_LEN) else {
+            return Ok(None);
+        };
+
+        let byte_order = header[0];
+        if !matches!(byte_order, b'B' | b'l') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an X11 connection setup",
+            ));
+        }
+
+        let u16_at = |offset: usize| {
+            let bytes = [header[offset], header[offset + 1]];
+            match byte_order {
+                b'B' => u16::from_be_bytes(bytes),
+                _ => u16::from_le_bytes(bytes),
+            }
+        };
+
+        let name_len = u16_at(6) as usize;
+        let data_len = u16_at(8) as usize;
+        let name_start = Self::HEADER_LEN;
+        let data_start = name_start + padded(name_len);
+        let len = data_start + padded(data_len);
+
+        if buf.len() < len {
+            return Ok(None);
+        }
+
+        let request = Self {
+            byte_order,
+            major_version: u16_at(2),
+            minor_version: u16_at(4),
+            auth_name: buf[name_start..name_start + name_len].to_vec(),
+            auth_data: buf[data_start..data_start + data_len].to_vec(),
+        };
+
+        Ok(Some((request, len)))
+    }
+
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        let too_long = || io::Error::new(io::ErrorKind::InvalidInput, "auth field too long");
+        let name_len = u16::try_from(self.auth_name.len()).map_err(|_| too_long())?;
+        let data_len = u16::try_from(self.auth_data.len()).map_err(|_| too_long())?;
+
+        let to_bytes = |value: u16| match self.byte_order {
+            b'B' => value.to_be_bytes(),
+            _ => value.to_le_bytes(),
+        };
+
+        let mut buf = vec![self.byte_order, 0];
+        for value in [
+            self.major_version,
+            self.minor_version,
+            name_len,
+            data_len,
+            0,
+        ] {
+            buf.extend_from_slice(&to_bytes(value));
+        }
+
+        for field in [&self.auth_name, &self.auth_data] {
+            buf.extend_from_slice(field);
+            buf.resize(padded(buf.len()), 0);
+        }
+
+        Ok(buf)
+    }
+}
+
+/// What to do with a client's connection setup, see `ProxyAuth::rewrite`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rewrite {
+    /// Read more from the client and try again
+    Incomplete,
+    /// The client's credentials aren't in the downstream authority. Close the connection
+    Rejected,
+    /// Send `setup` upstream in place of the first `consumed` bytes from the client
+    Forward { setup: Vec<u8>, consumed: usize },
+}
+
+/// Credential handling for an X11 proxy or forwarder: clients authenticate
+/// against `downstream`, the proxy against the upstream server with its entry in `upstream`
+pub struct ProxyAuth {
+    downstream: Authority,
+    upstream: Authority,
+    display: DisplayName,
+}
+
+impl ProxyAuth {
+    /// `display` is the upstream server's, used to pick its entry from `upstream`
+    pub fn new(downstream: Authority, upstream: Authority, display: DisplayName) -> Self {
+        Self {
+            downstream,
+            upstream,
+            display,
+        }
+    }
+
+    /// Checks the client's credentials at the start of `buf`,
+    /// then swaps in the upstream server's cookie
+    pub fn rewrite(&self, buf: &[u8]) -> io::Result<Rewrite> {
+        let Some((mut request, consumed)) = SetupRequest::parse(buf)? else {
+            return Ok(Rewrite::Incomplete);
+        };
+
+        if !self
+            .downstream
+            .accepts(&request.auth_name, &request.auth_data)
+        {
+            return Ok(Rewrite::Rejected);
+        }
+
+        let Some(entry) = self.upstream.get_auth_for_display(&self.display)? else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no entry for upstream display {}", self.display),
+            ));
+        };
+
+        // The cookie is about to leave the process
+        audit::check(SecretAccess::Serialize, &entry.auth_name)?;
+        request.auth_name = entry.auth_name.as_bytes().to_vec();
+        request.auth_data = entry.auth_data_unobserved().to_vec();
+
+        Ok(Rewrite::Forward {
+            setup: request.encode()?,
+            consumed,
+        })
+    }
+}
@@ -0,0 +1,98 @@
+use std::{fmt::Write, time::SystemTime};
+
+use crate::{Authority, encoding::Entry, file::AuthorityInfo};
+
+// Report types only hold plain data, so frontends can serialize and render them as they like
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// A line of `xauth list`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListEntry {
+    /// See `Entry::display_name`
+    pub display: String,
+    pub protocol: String,
+    /// Hex, only in `ListOutput::revealed`
+    pub data: Option<String>,
+}
+
+/// What `xauth list` shows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListOutput {
+    pub entries: Vec<ListEntry>,
+}
+
+impl ListOutput {
+    /// Without the auth data
+    pub fn new(authority: &Authority) -> Self {
+        Self::build(authority, |_| None)
+    }
+
+    /// With the auth data, for when the user explicitly asked for it
+    pub fn revealed(authority: &Authority) -> Self {
+        Self::build(authority, |entry| Some(hex(entry.auth_data())))
+    }
+
+    fn build(authority: &Authority, data: impl Fn(&Entry) -> Option<String>) -> Self {
+        let entries = authority
+            .iter()
+            .map(|entry| ListEntry {
+                display: entry.display_name(),
+                protocol: entry.auth_name.to_string(),
+                data: data(entry),
+            })
+            .collect();
+
+        Self { entries }
+    }
+}
+
+/// `AuthorityInfo` in plain data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InfoOutput {
+    pub path: Option<String>,
+    pub locked: bool,
+    pub entries: usize,
+    pub size: u64,
+    /// Seconds since the epoch
+    pub modified: Option<u64>,
+}
+
+impl From<&AuthorityInfo> for InfoOutput {
+    fn from(info: &AuthorityInfo) -> Self {
+        Self {
+            path: info.path.as_ref().map(|path| path.display().to_string()),
+            locked: info.locked,
+            entries: info.entries,
+            size: info.size,
+            modified: info
+                .modified
+                .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs()),
+        }
+    }
+}
+
+/// Problems with an authority file, see `AuthorityFile::audit`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditOutput {
+    pub path: Option<String>,
+    pub owner: u32,
+    /// Permission bits
+    pub mode: u32,
+    /// Owned by the effective uid
+    pub owned_by_us: bool,
+    /// Writable by group or others, see `AuthorityFile::verify_ownership`
+    pub writable_by_others: bool,
+    /// Entries `Entry::validate` rejects
+    pub invalid_entries: usize,
+    /// Entries never used, as an earlier one has the same key
+    pub shadowed_entries: usize,
+    /// What a lenient read had to skip
+    pub parse_warnings: Vec<String>,
+}
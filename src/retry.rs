@@ -0,0 +1,153 @@
+use std::{
+    io, process, thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::hash::Fnv1a;
+
+const ESTALE: i32 = 116;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    Fixed(Duration),
+    /// Doubles after every attempt, up to `max`
+    Exponential {
+        initial: Duration,
+        max: Duration,
+    },
+}
+
+/// How often, and how patiently, to retry operations that may succeed a moment later:
+/// a busy lock, an interrupted or would-block call, a stale NFS handle.
+///
+/// Taken by `AuthorityFile::open_with_retry`, `LockOptions` and `Broker::retry`. For the
+/// other file APIs, set it on the lock strategy: `Fs::with_lock_strategy(DotLock::default().retry(policy))`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    attempts: u32,
+    backoff: Backoff,
+    jitter: bool,
+    deadline: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    /// No retries
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl RetryPolicy {
+    pub const fn none() -> Self {
+        Self {
+            attempts: 1,
+            backoff: Backoff::Fixed(Duration::ZERO),
+            jitter: false,
+            deadline: None,
+        }
+    }
+
+    /// `attempts` includes the first one
+    pub const fn fixed(attempts: u32, delay: Duration) -> Self {
+        Self {
+            attempts,
+            backoff: Backoff::Fixed(delay),
+            jitter: false,
+            deadline: None,
+        }
+    }
+
+    pub const fn exponential(attempts: u32, initial: Duration, max: Duration) -> Self {
+        Self {
+            attempts,
+            backoff: Backoff::Exponential { initial, max },
+            jitter: false,
+            deadline: None,
+        }
+    }
+
+    /// Wait a random part of each delay, so contending processes don't retry in lockstep
+    pub const fn jitter(mut self, enabled: bool) -> Self {
+        self.jitter = enabled;
+        self
+    }
+
+    /// Give up once this much time has passed since the first attempt, even with attempts left
+    pub const fn deadline(mut self, deadline: Option<Duration>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// The wait after the `failures`th failed attempt
+    pub fn delay(&self, failures: u32) -> Duration {
+        let delay = match self.backoff {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { initial, max } => initial
+                .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+                .min(max),
+        };
+
+        match self.jitter {
+            true => delay.mul_f64(random_fraction(failures)),
+            false => delay,
+        }
+    }
+
+    /// Whether `error` is worth another attempt
+    pub fn is_transient(error: &io::Error) -> bool {
+        matches!(
+            error.kind(),
+            io::ErrorKind::AlreadyExists | io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+        ) || Self::is_stale_handle(error)
+    }
+
+    /// NFS's ESTALE: the file was replaced on the server, only reopening it helps
+    pub(crate) fn is_stale_handle(error: &io::Error) -> bool {
+        error.raw_os_error() == Some(ESTALE)
+    }
+
+    /// Runs `op` until it succeeds, fails with an error `retry` rejects, or attempts run out
+    pub(crate) fn run<T>(
+        &self,
+        mut op: impl FnMut() -> io::Result<T>,
+        retry: impl Fn(&io::Error) -> bool,
+    ) -> io::Result<T> {
+        let start = Instant::now();
+        let mut failures = 0;
+
+        loop {
+            match op() {
+                Err(e) if failures + 1 < self.attempts && retry(&e) => {
+                    failures += 1;
+                    let mut delay = self.delay(failures);
+
+                    if let Some(deadline) = self.deadline {
+                        let remaining = deadline.saturating_sub(start.elapsed());
+                        if remaining.is_zero() {
+                            return Err(e);
+                        }
+                        delay = delay.min(remaining);
+                    }
+
+                    thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Good enough to spread out retries, nothing more
+fn random_fraction(salt: u32) -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+
+    let mut hasher = Fnv1a::new();
+    hasher.update(&nanos.to_be_bytes());
+    hasher.update(&process::id().to_be_bytes());
+    hasher.update(&salt.to_be_bytes());
+
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,119 @@
+use std::{fmt, io};
+
+use crate::{
+    Entry,
+    audit::{self, Redacted, SecretAccess},
+    encoding::{Family, shared_name},
+};
+
+/// An entry borrowing all fields from the buffer it was parsed from
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct EntryRef<'a> {
+    pub family: Family,
+    pub address: &'a [u8],
+    pub display_number: &'a str,
+    pub auth_name: &'a str,
+    auth_data: &'a [u8],
+}
+
+impl fmt::Debug for EntryRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EntryRef")
+            .field("family", &self.family)
+            .field("address", &self.address)
+            .field("display_number", &self.display_number)
+            .field("auth_name", &self.auth_name)
+            .field("auth_data", &Redacted(self.auth_data))
+            .finish()
+    }
+}
+
+impl<'a> EntryRef<'a> {
+    /// Like `Entry::auth_data`, notifies the secret access observer
+    pub fn auth_data(&self) -> &'a [u8] {
+        audit::notify(SecretAccess::RawData, self.auth_name);
+        self.auth_data
+    }
+
+    pub fn to_entry(&self) -> Entry {
+        Entry::from_parts(
+            self.family,
+            self.address.to_vec(),
+            self.display_number.to_string(),
+            shared_name(self.auth_name),
+            self.auth_data.to_vec(),
+        )
+    }
+}
+
+/// Iterator over the entries in an in-memory authority file, without copying them.
+/// Reading the whole file into one buffer and scanning it costs a single allocation,
+/// for audit-style workloads that look at every entry and keep none
+pub struct EntryScanner<'a> {
+    bytes: &'a [u8],
+    failed: bool,
+}
+
+impl<'a> EntryScanner<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            failed: false,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn take_len(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn take_field(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.take_len()?;
+        self.take(len as usize)
+    }
+
+    fn take_str(&mut self, field: &str) -> io::Result<&'a str> {
+        str::from_utf8(self.take_field()?).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("scanned entry: {field} is not valid UTF-8"),
+            )
+        })
+    }
+
+    fn next_entry(&mut self) -> io::Result<EntryRef<'a>> {
+        Ok(EntryRef {
+            family: Family::from_code(self.take_len()?),
+            address: self.take_field()?,
+            display_number: self.take_str("display_number")?,
+            auth_name: self.take_str("auth_name")?,
+            auth_data: self.take_field()?,
+        })
+    }
+}
+
+impl<'a> Iterator for EntryScanner<'a> {
+    type Item = io::Result<EntryRef<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.bytes.is_empty() {
+            return None;
+        }
+
+        let entry = self.next_entry();
+        // Past a bad entry, field boundaries can't be trusted
+        self.failed = entry.is_err();
+
+        Some(entry)
+    }
+}
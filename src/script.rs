@@ -0,0 +1,147 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+};
+
+use crate::{
+    Authority, MIT_MAGIC_COOKIE_1,
+    compat::CompatOptions,
+    display::DisplayName,
+    encoding::{Entry, shared_name},
+    error::{Error, Result},
+    key::EntryKey,
+};
+
+fn key_for(display: &str) -> std::result::Result<EntryKey, String> {
+    let display: DisplayName = display.parse().map_err(|e: Error| e.to_string())?;
+    EntryKey::from_display_target(&display).map_err(|e| e.to_string())
+}
+
+fn parse_hex(key: &str) -> Option<Vec<u8>> {
+    // Like xauth, also take the key as a quoted string
+    if let Some(key) = key.strip_prefix('"').and_then(|key| key.strip_suffix('"')) {
+        return Some(key.as_bytes().to_vec());
+    }
+
+    if !key.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..key.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(key.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn read_file(path: &str) -> io::Result<Authority> {
+    match path {
+        "-" => Authority::read_from(&mut io::stdin().lock()),
+        path => Authority::read_from(&mut File::open(path)?),
+    }
+}
+
+fn write_file(path: &str, authority: &Authority) -> io::Result<()> {
+    let mut writer: Box<dyn Write> = match path {
+        "-" => Box::new(io::stdout().lock()),
+        path => Box::new(
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(path)?,
+        ),
+    };
+
+    authority.write_with(&mut writer, &CompatOptions::default())?;
+    writer.flush()
+}
+
+impl Authority {
+    /// Runs xauth commands, one per line, like `xauth source` does.
+    ///
+    /// Supports `add`, `remove`, `merge` and `extract`, with `-` for stdin/stdout.
+    /// Blank lines and `#` comments are skipped. Stops at the first failing line,
+    /// keeping the changes made by the lines before it
+    pub fn apply_script(&mut self, script: &str) -> Result<()> {
+        for (index, line) in script.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let Some((command, args)) = words.split_first() else {
+                continue;
+            };
+
+            if command.starts_with('#') {
+                continue;
+            }
+
+            self.run_command(command, args)
+                .map_err(|message| Error::Script {
+                    line: index + 1,
+                    message,
+                })?;
+        }
+
+        Ok(())
+    }
+
+    fn run_command(&mut self, command: &str, args: &[&str]) -> std::result::Result<(), String> {
+        match (command, args) {
+            ("add", [display, protocol, key]) => {
+                let key_for_display = key_for(display)?;
+                // xauth's shorthand
+                let protocol = match *protocol {
+                    "." => MIT_MAGIC_COOKIE_1,
+                    protocol => protocol,
+                };
+                let auth_data = parse_hex(key).ok_or("key is not a hex string")?;
+
+                let entry = Entry::from_parts(
+                    key_for_display.family,
+                    key_for_display.address,
+                    key_for_display.display_number,
+                    shared_name(protocol),
+                    auth_data,
+                );
+                entry.validate().map_err(|e| e.to_string())?;
+
+                self.merge(Authority::new(Some(vec![entry])));
+            }
+            ("add", _) => return Err("usage: add displayname protocolname hexkey".into()),
+
+            ("remove", [_, ..]) => {
+                for display in args {
+                    let key = key_for(display)?;
+                    self.0.retain(|entry| !entry.is_for(&key));
+                }
+            }
+            ("merge", [_, ..]) => {
+                for path in args {
+                    let other = read_file(path).map_err(|e| format!("{path}: {e}"))?;
+                    self.merge(other);
+                }
+            }
+            ("extract", [path, displays @ ..]) if !displays.is_empty() => {
+                let keys = displays
+                    .iter()
+                    .map(|display| key_for(display))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+
+                let extracted = self
+                    .iter()
+                    .filter(|entry| keys.iter().any(|key| entry.is_for(key)))
+                    .cloned()
+                    .collect();
+
+                write_file(path, &Authority::new(Some(extracted)))
+                    .map_err(|e| format!("{path}: {e}"))?;
+            }
+            ("remove" | "merge" | "extract", _) => {
+                return Err(format!("{command}: missing arguments"));
+            }
+            _ => return Err(format!("unsupported command: {command}")),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    alloc::{self, Layout},
+    ops::Deref,
+    ptr::{self, NonNull},
+    slice,
+    sync::atomic::{Ordering, compiler_fence},
+};
+
+unsafe extern "C" {
+    fn mlock(addr: *const u8, len: usize) -> i32;
+    fn munlock(addr: *const u8, len: usize) -> i32;
+    fn sysconf(name: i32) -> i64;
+    #[cfg(target_os = "linux")]
+    fn madvise(addr: *mut u8, len: usize, advice: i32) -> i32;
+}
+
+#[cfg(target_os = "linux")]
+const SC_PAGESIZE: i32 = 30;
+#[cfg(not(target_os = "linux"))]
+const SC_PAGESIZE: i32 = 29; // BSDs and macOS
+#[cfg(target_os = "linux")]
+const MADV_DONTDUMP: i32 = 16;
+
+fn page_size() -> usize {
+    match unsafe { sysconf(SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+/// Bytes on pages of their own, kept out of swap and core dumps, and zeroed on drop.
+/// Both protections are best effort: e.g. mlock fails past RLIMIT_MEMLOCK
+pub struct SecretBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+    layout: Layout,
+    locked: bool,
+}
+
+// SecretBuf uniquely owns its allocation
+unsafe impl Send for SecretBuf {}
+unsafe impl Sync for SecretBuf {}
+
+impl SecretBuf {
+    pub fn new(bytes: &[u8]) -> Self {
+        let page = page_size();
+        // Whole pages, so protecting them doesn't affect unrelated allocations
+        let size = bytes.len().max(1).div_ceil(page) * page;
+        let layout = Layout::from_size_align(size, page).expect("page size is a power of two");
+
+        let ptr = NonNull::new(unsafe { alloc::alloc_zeroed(layout) })
+            .unwrap_or_else(|| alloc::handle_alloc_error(layout));
+
+        #[cfg(target_os = "linux")]
+        unsafe {
+            madvise(ptr.as_ptr(), size, MADV_DONTDUMP);
+        }
+
+        let locked = unsafe { mlock(ptr.as_ptr(), size) } == 0;
+
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), ptr.as_ptr(), bytes.len()) };
+
+        Self {
+            ptr,
+            len: bytes.len(),
+            layout,
+            locked,
+        }
+    }
+
+    /// Whether mlock succeeded, i.e. the bytes can't be swapped out
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl Deref for SecretBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for SecretBuf {
+    fn drop(&mut self) {
+        unsafe {
+            for i in 0..self.layout.size() {
+                ptr::write_volatile(self.ptr.as_ptr().add(i), 0);
+            }
+            compiler_fence(Ordering::SeqCst);
+
+            if self.locked {
+                munlock(self.ptr.as_ptr(), self.layout.size());
+            }
+
+            alloc::dealloc(self.ptr.as_ptr(), self.layout);
+        }
+    }
+}
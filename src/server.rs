@@ -0,0 +1,110 @@
+use std::{
+    ffi::OsString,
+    hint, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Authority, Cookie, Scope, Target, display::DisplayName, encoding::Entry, error::Result,
+    file::CreateOptions,
+};
+
+/// Builds the file an X server is started with, via `-auth <path>`
+#[derive(Debug, Default)]
+pub struct ServerAuthBuilder {
+    entries: Vec<Entry>,
+}
+
+impl ServerAuthBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept `cookie` from clients in `scope`
+    pub fn cookie(mut self, cookie: &Cookie, scope: Scope) -> Self {
+        // The server doesn't care about display numbers, only that entries are distinct
+        let slot = u16::try_from(self.entries.len()).unwrap_or(u16::MAX);
+        self.entries
+            .push(Entry::new(cookie, scope, Target::Server { slot }));
+        self
+    }
+
+    pub fn entry(mut self, entry: Entry) -> Self {
+        self.entries.push(entry);
+        self
+    }
+
+    /// Creates the file at `path` with mode 0600 and writes the entries.
+    /// Fails if `path` exists, so pick a fresh one per server
+    pub fn write_to_path(self, path: &Path) -> Result<ServerAuth> {
+        let mut file = CreateOptions::new().create(path)?;
+        file.set(Authority::new(Some(self.entries)))?;
+
+        Ok(ServerAuth {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+/// A written server authority, see `ServerAuthBuilder`
+#[derive(Debug, Clone)]
+pub struct ServerAuth {
+    path: PathBuf,
+}
+
+impl ServerAuth {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `-auth <path>`, to splice into an Xorg or Xephyr command line
+    pub fn args(&self) -> [OsString; 2] {
+        ["-auth".into(), self.path.clone().into_os_string()]
+    }
+}
+
+/// Whether a client using `client_auth` could connect to a server started with `server_auth`
+/// on `display`: the entry clients pick for `display` must carry a cookie the server accepts.
+///
+/// Catches the "black screen, no clients can connect" class of launcher bugs before exec
+pub fn verify_pair(
+    server_auth: &Authority,
+    client_auth: &Authority,
+    display: &DisplayName,
+) -> io::Result<bool> {
+    let Some(client) = client_auth.get_auth_for_display(display)? else {
+        return Ok(false);
+    };
+
+    // Servers only compare the protocol and data, the rest of their entries is ignored
+    Ok(server_auth.iter().any(|server| {
+        server.auth_name == client.auth_name
+            && server.auth_data_unobserved() == client.auth_data_unobserved()
+    }))
+}
+
+/// Compares without exiting early, so timing doesn't tell how much of a guess was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    // Cookie lengths are fixed per protocol, leaking them is fine
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let diff = a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b));
+    hint::black_box(diff) == 0
+}
+
+impl Authority {
+    /// Whether a server with this authority accepts a client that sent `auth_name`
+    /// and `auth_data` in its connection setup, for X proxies and nested servers.
+    ///
+    /// Every entry is compared, in constant time. Only meaningful for protocols
+    /// whose clients send the data as-is, like MIT-MAGIC-COOKIE-1
+    pub fn accepts(&self, auth_name: &[u8], auth_data: &[u8]) -> bool {
+        self.iter().fold(false, |accepted, entry| {
+            let matches = entry.auth_name.as_bytes() == auth_name
+                && constant_time_eq(entry.auth_data_unobserved(), auth_data);
+            accepted | matches
+        })
+    }
+}
@@ -0,0 +1,324 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{
+        Arc, Condvar, Mutex,
+        mpsc::{self, RecvTimeoutError},
+    },
+    task::{Context, Poll, Waker},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Authority,
+    error::{Error, Result},
+    file::AuthorityFile,
+    storage::{Fs, Storage},
+};
+
+struct Slot<T> {
+    value: Option<Result<T>>,
+    waker: Option<Waker>,
+}
+
+type Shared<T> = Arc<(Mutex<Slot<T>>, Condvar)>;
+
+/// The result of a request to an `AuthorityService`.
+/// Either `.await` it, or block on `wait`
+pub struct Ticket<T>(Shared<T>);
+
+impl<T> Ticket<T> {
+    pub fn wait(self) -> Result<T> {
+        let (slot, ready) = &*self.0;
+        let mut slot = slot.lock().unwrap_or_else(|e| e.into_inner());
+
+        loop {
+            if let Some(value) = slot.value.take() {
+                return value;
+            }
+
+            slot = ready.wait(slot).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+impl<T> Future for Ticket<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.0.0.lock().unwrap_or_else(|e| e.into_inner());
+
+        match slot.value.take() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The worker's end of a `Ticket`
+struct Completer<T>(Option<Shared<T>>);
+
+impl<T> Completer<T> {
+    fn complete(mut self, value: Result<T>) {
+        if let Some(shared) = self.0.take() {
+            Self::fill(&shared, value);
+        }
+    }
+
+    fn fill(shared: &Shared<T>, value: Result<T>) {
+        let (slot, ready) = &**shared;
+        let mut slot = slot.lock().unwrap_or_else(|e| e.into_inner());
+
+        slot.value = Some(value);
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+        ready.notify_all();
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    // Requests dropped by a panicking or stopped worker mustn't leave waiters hanging
+    fn drop(&mut self) {
+        if let Some(shared) = self.0.take() {
+            Self::fill(&shared, Err(stopped()));
+        }
+    }
+}
+
+fn ticket<T>() -> (Ticket<T>, Completer<T>) {
+    let shared = Arc::new((
+        Mutex::new(Slot {
+            value: None,
+            waker: None,
+        }),
+        Condvar::new(),
+    ));
+
+    (Ticket(shared.clone()), Completer(Some(shared)))
+}
+
+fn stopped() -> Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "authority service stopped").into()
+}
+
+/// A failed write is reported to every request of the batch
+fn share(error: &Error) -> Error {
+    match error {
+        Error::Io(e) => io::Error::new(e.kind(), e.to_string()).into(),
+        Error::FieldTooLong(e) => Error::FieldTooLong(e.clone()),
+        Error::InvalidEntry(reason) => Error::InvalidEntry(reason),
+        Error::LockLost => Error::LockLost,
+        e => io::Error::other(e.to_string()).into(),
+    }
+}
+
+/// Run once the batch is written, with the error if it wasn't
+type Completion = Box<dyn FnOnce(Option<&Error>)>;
+/// A modification, returning its completion
+type Job = Box<dyn FnOnce(&mut Authority) -> Completion + Send>;
+
+enum Request {
+    Modify(Job),
+    Read(Box<dyn FnOnce(&Authority) + Send>),
+}
+
+/// How an `AuthorityService` batches writes, in the style of `std::fs::OpenOptions`
+#[derive(Debug, Clone, Default)]
+pub struct ServiceOptions {
+    quiescence: Duration,
+    max_delay: Option<Duration>,
+}
+
+impl ServiceOptions {
+    /// Writes as soon as the queue is empty
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait for `window` without new requests before writing, so a flurry of
+    /// modifications ends up in a single rewrite
+    pub fn coalesce(mut self, window: Duration) -> Self {
+        self.quiescence = window;
+        self
+    }
+
+    /// Write at most `delay` after the first pending modification,
+    /// even if requests keep coming in
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = Some(delay);
+        self
+    }
+
+    fn wait(&self, since: Instant) -> Duration {
+        match self.max_delay {
+            Some(delay) => self.quiescence.min(delay.saturating_sub(since.elapsed())),
+            None => self.quiescence,
+        }
+    }
+}
+
+/// Holds a locked authority file on a worker thread, applying requests from any
+/// number of threads or tasks one at a time.
+///
+/// Modifications queued while the worker is busy are written together, in one rewrite.
+/// See `ServiceOptions` to hold writes back for longer
+pub struct AuthorityService<S: Storage = Fs> {
+    sender: Option<mpsc::Sender<Request>>,
+    worker: Option<JoinHandle<Result<AuthorityFile<S>>>>,
+}
+
+impl<S: Storage> AuthorityService<S>
+where
+    AuthorityFile<S>: Send + 'static,
+{
+    pub fn start(file: AuthorityFile<S>) -> Result<Self> {
+        Self::start_with(file, ServiceOptions::new())
+    }
+
+    pub fn start_with(mut file: AuthorityFile<S>, options: ServiceOptions) -> Result<Self> {
+        // The lock is held throughout, so reading once is enough
+        let authority = file.get()?;
+        let (sender, receiver) = mpsc::channel();
+
+        let worker = thread::Builder::new()
+            .name("authority-service".into())
+            .spawn(move || run(file, authority, receiver, options))?;
+
+        Ok(Self {
+            sender: Some(sender),
+            worker: Some(worker),
+        })
+    }
+
+    /// Runs `f` on the authority, then writes it. Completes once the write did
+    pub fn modify<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Authority) -> T + Send + 'static,
+    ) -> Ticket<T> {
+        let (ticket, completer) = ticket();
+
+        let job: Job = Box::new(move |authority| {
+            let value = f(authority);
+            Box::new(move |error| match error {
+                Some(error) => completer.complete(Err(share(error))),
+                None => completer.complete(Ok(value)),
+            })
+        });
+
+        self.send(Request::Modify(job));
+        ticket
+    }
+
+    /// Runs `f` on the authority, seeing every modification requested before
+    pub fn read<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Authority) -> T + Send + 'static,
+    ) -> Ticket<T> {
+        let (ticket, completer) = ticket();
+        self.send(Request::Read(Box::new(move |authority| {
+            completer.complete(Ok(f(authority)))
+        })));
+        ticket
+    }
+
+    fn send(&self, request: Request) {
+        if let Some(sender) = &self.sender {
+            // A stopped worker drops the request, failing its ticket
+            let _ = sender.send(request);
+        }
+    }
+
+    /// Finishes queued requests and hands back the file, still locked
+    pub fn shutdown(mut self) -> Result<AuthorityFile<S>> {
+        self.sender.take();
+
+        match self.worker.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            _ => Err(io::Error::other("authority service worker panicked").into()),
+        }
+    }
+}
+
+impl<S: Storage> Drop for AuthorityService<S> {
+    fn drop(&mut self) {
+        self.sender.take();
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn run<S: Storage>(
+    mut file: AuthorityFile<S>,
+    mut authority: Authority,
+    receiver: mpsc::Receiver<Request>,
+    options: ServiceOptions,
+) -> Result<AuthorityFile<S>> {
+    let mut batch = Batch::default();
+
+    // Modifications are held back until the queue stays quiet for the window.
+    // Reads write out pending modifications first, so they never see unwritten state
+    loop {
+        let request = match batch.since {
+            None => receiver.recv().ok(),
+            Some(since) => match receiver.recv_timeout(options.wait(since)) {
+                Ok(request) => Some(request),
+                Err(RecvTimeoutError::Timeout) => {
+                    batch.flush(&mut file, &mut authority);
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => None,
+            },
+        };
+
+        match request {
+            Some(Request::Modify(job)) => {
+                batch.completions.push(job(&mut authority));
+                batch.since.get_or_insert_with(Instant::now);
+            }
+            Some(Request::Read(job)) => {
+                batch.flush(&mut file, &mut authority);
+                job(&authority);
+            }
+            None => break,
+        }
+    }
+
+    batch.flush(&mut file, &mut authority);
+    Ok(file)
+}
+
+#[derive(Default)]
+struct Batch {
+    completions: Vec<Completion>,
+    /// When the first unwritten modification came in
+    since: Option<Instant>,
+}
+
+impl Batch {
+    fn flush<S: Storage>(&mut self, file: &mut AuthorityFile<S>, authority: &mut Authority) {
+        if self.since.take().is_none() {
+            return;
+        }
+
+        let result = file.set_ref(authority);
+
+        // Nothing was written, go back to what's in the file
+        if result.is_err()
+            && let Ok(current) = file.get()
+        {
+            *authority = current;
+        }
+
+        for complete in self.completions.drain(..) {
+            complete(result.as_ref().err());
+        }
+    }
+}
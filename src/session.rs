@@ -0,0 +1,101 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    encoding::Entry,
+    error::{Error, Result},
+    file::{AuthorityFile, ChangeSet},
+};
+
+/// Undoes a session's authority setup on drop, or on `teardown` to see errors.
+///
+/// Locks are released first, then the session's changes to shared authorities
+/// are reverted, then server-side files are removed
+#[derive(Default)]
+pub struct SessionAuthGuard {
+    files: Vec<AuthorityFile>,
+    changes: Vec<(PathBuf, ChangeSet)>,
+    remove: Vec<PathBuf>,
+}
+
+impl SessionAuthGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep `file` locked until teardown
+    pub fn hold(&mut self, file: AuthorityFile) {
+        self.files.push(file);
+    }
+
+    /// On teardown, remove exactly these entries from the authority at `path`.
+    /// Entries added by other tools in the meantime are kept
+    pub fn prune_on_teardown(&mut self, path: &Path, entries: Vec<Entry>) {
+        self.revert_on_teardown(
+            path,
+            ChangeSet {
+                added: entries,
+                removed: Vec::new(),
+            },
+        );
+    }
+
+    /// On teardown, `apply_revert` these changes to the authority at `path`
+    pub fn revert_on_teardown(&mut self, path: &Path, changes: ChangeSet) {
+        self.changes.push((path.to_path_buf(), changes));
+    }
+
+    /// On teardown, remove the file at `path`, e.g. the server's `-auth` file
+    pub fn remove_on_teardown(&mut self, path: &Path) {
+        self.remove.push(path.to_path_buf());
+    }
+
+    /// Undoes everything, continuing past failures. Returns the first one
+    pub fn teardown(mut self) -> Result<()> {
+        self.run()
+    }
+
+    fn run(&mut self) -> Result<()> {
+        let mut result = Ok(());
+
+        // Reverting below needs the locks we may hold
+        self.files.clear();
+
+        for (path, changes) in self.changes.drain(..) {
+            let reverted = revert(&path, changes);
+            if result.is_ok() {
+                result = reverted;
+            }
+        }
+
+        for path in self.remove.drain(..) {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound && result.is_ok() => {
+                    result = Err(e.into())
+                }
+                _ => {}
+            }
+        }
+
+        result
+    }
+}
+
+fn revert(path: &Path, changes: ChangeSet) -> Result<()> {
+    let mut file = match AuthorityFile::open(path) {
+        Ok(file) => file,
+        // Someone removed the whole file, nothing left to revert
+        Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    file.apply_revert(changes)
+}
+
+impl Drop for SessionAuthGuard {
+    fn drop(&mut self) {
+        let _ = self.run();
+    }
+}
@@ -0,0 +1,458 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, DirBuilder, File, OpenOptions},
+    io::{self, Cursor, Read, Seek, Write},
+    marker::PhantomData,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use crate::lock::{DotLock, LockGuard, LockStrategy};
+
+pub trait StorageFile: Read + Write + Seek {
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+    /// False for fifos, sockets, devices and the like
+    fn is_regular(&self) -> io::Result<bool>;
+    /// Sets permission bits exactly, regardless of umask
+    fn set_mode(&mut self, mode: u32) -> io::Result<()>;
+    /// None where modification times aren't tracked
+    fn modified(&self) -> io::Result<Option<SystemTime>>;
+    /// Whether `set_len` can shrink the file. If not, `AuthorityFile::set`
+    /// refuses to write less than what's already there
+    fn can_truncate(&self) -> bool {
+        true
+    }
+
+    /// The file at `path` opened anew, when the current handle went stale.
+    /// None where files can't go stale
+    fn reopen(&self, _path: &Path) -> io::Result<Option<Self>>
+    where
+        Self: Sized,
+    {
+        Ok(None)
+    }
+}
+
+impl StorageFile for File {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+
+    fn is_regular(&self) -> io::Result<bool> {
+        Ok(self.metadata()?.is_file())
+    }
+
+    fn set_mode(&mut self, mode: u32) -> io::Result<()> {
+        self.set_permissions(fs::Permissions::from_mode(mode))
+    }
+
+    fn modified(&self) -> io::Result<Option<SystemTime>> {
+        self.metadata()?.modified().map(Some)
+    }
+
+    fn reopen(&self, path: &Path) -> io::Result<Option<Self>> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map(Some)
+    }
+}
+
+impl StorageFile for Cursor<Vec<u8>> {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.get_mut().resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn is_regular(&self) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    fn set_mode(&mut self, _mode: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn modified(&self) -> io::Result<Option<SystemTime>> {
+        Ok(None)
+    }
+}
+
+/// Adapts any `Read + Write + Seek` for use with `AuthorityFile::from_stream`,
+/// e.g. an encrypted container or a custom transport.
+///
+/// Streams can't be truncated, so the contents can only ever grow
+pub struct Stream<T>(pub T);
+
+impl<T: Read> Read for Stream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: Write> Write for Stream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: Seek> Seek for Stream<T> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl<T: Read + Write + Seek> StorageFile for Stream<T> {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        let position = self.0.stream_position()?;
+        let end = self.0.seek(io::SeekFrom::End(0))?;
+        self.0.seek(io::SeekFrom::Start(position))?;
+
+        match len >= end {
+            // Writes already extended it
+            true => Ok(()),
+            false => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "streams can't be truncated",
+            )),
+        }
+    }
+
+    fn is_regular(&self) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    fn set_mode(&mut self, _mode: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn modified(&self) -> io::Result<Option<SystemTime>> {
+        Ok(None)
+    }
+
+    fn can_truncate(&self) -> bool {
+        false
+    }
+}
+
+/// Everything `AuthorityFile` needs from the place authorities are kept
+pub trait Storage {
+    type File: StorageFile;
+    type Lock: LockGuard;
+
+    fn open(&self, path: &Path) -> io::Result<Self::File>;
+    /// For files that won't be written. Opens them like `open` by default
+    fn open_read_only(&self, path: &Path) -> io::Result<Self::File> {
+        self.open(path)
+    }
+    /// Should fail if the path already exists.
+    /// `mode` is subject to the process umask, like with open(2)
+    fn create(&self, path: &Path, mode: u32) -> io::Result<Self::File>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn remove(&self, path: &Path) -> io::Result<()>;
+    fn lock(&self, path: &Path) -> io::Result<Self::Lock>;
+    /// Like `mkdir -p`, only applying `mode` to directories it creates
+    fn create_dir_all(&self, path: &Path, mode: u32) -> io::Result<()>;
+}
+
+/// The real filesystem
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fs<L: LockStrategy = DotLock> {
+    lock_strategy: L,
+}
+
+impl<L: LockStrategy> Fs<L> {
+    pub fn with_lock_strategy(lock_strategy: L) -> Self {
+        Self { lock_strategy }
+    }
+}
+
+impl<L: LockStrategy> Storage for Fs<L> {
+    type File = File;
+    type Lock = L::Guard;
+
+    fn open(&self, path: &Path) -> io::Result<File> {
+        OpenOptions::new().read(true).write(true).open(path)
+    }
+
+    fn open_read_only(&self, path: &Path) -> io::Result<File> {
+        File::open(path)
+    }
+
+    fn create(&self, path: &Path, mode: u32) -> io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .mode(mode)
+            .create_new(true)
+            .open(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn lock(&self, path: &Path) -> io::Result<L::Guard> {
+        self.lock_strategy.acquire(path)
+    }
+
+    fn create_dir_all(&self, path: &Path, mode: u32) -> io::Result<()> {
+        DirBuilder::new().recursive(true).mode(mode).create(path)
+    }
+}
+
+/// Storage of files that aren't reachable by path, see `AuthorityFile::from_stream`.
+/// Every path based operation fails
+pub struct Detached<F>(PhantomData<F>);
+
+impl<F: StorageFile> Storage for Detached<F> {
+    type File = F;
+    type Lock = NoLock;
+
+    fn open(&self, _path: &Path) -> io::Result<F> {
+        Err(Self::unsupported())
+    }
+
+    fn create(&self, _path: &Path, _mode: u32) -> io::Result<F> {
+        Err(Self::unsupported())
+    }
+
+    fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Err(Self::unsupported())
+    }
+
+    fn remove(&self, _path: &Path) -> io::Result<()> {
+        Err(Self::unsupported())
+    }
+
+    fn lock(&self, _path: &Path) -> io::Result<NoLock> {
+        Err(Self::unsupported())
+    }
+
+    fn create_dir_all(&self, _path: &Path, _mode: u32) -> io::Result<()> {
+        Err(Self::unsupported())
+    }
+}
+
+impl<F> Detached<F> {
+    fn unsupported() -> io::Error {
+        io::Error::new(io::ErrorKind::Unsupported, "detached files have no paths")
+    }
+}
+
+/// For files nothing else can reach, so there's nothing to lock out
+pub struct NoLock;
+
+impl LockGuard for NoLock {
+    fn still_held(&self) -> io::Result<bool> {
+        Ok(true)
+    }
+}
+
+type Contents = Arc<Mutex<Vec<u8>>>;
+
+fn lock_ignore_poison<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // None of the critical sections can leave the data half-updated
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A process-local filesystem, shared between clones
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    files: Arc<Mutex<HashMap<PathBuf, Contents>>>,
+    locks: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contents(&self, path: &Path) -> Option<Vec<u8>> {
+        let files = lock_ignore_poison(&self.files);
+        files.get(path).map(|data| lock_ignore_poison(data).clone())
+    }
+
+    fn not_found() -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, "no such file in memory storage")
+    }
+}
+
+impl Storage for MemoryStorage {
+    type File = MemoryFile;
+    type Lock = MemoryLock;
+
+    fn open(&self, path: &Path) -> io::Result<MemoryFile> {
+        let files = lock_ignore_poison(&self.files);
+        let data = files.get(path).ok_or_else(Self::not_found)?;
+
+        Ok(MemoryFile::new(data.clone()))
+    }
+
+    fn create(&self, path: &Path, _mode: u32) -> io::Result<MemoryFile> {
+        let mut files = lock_ignore_poison(&self.files);
+
+        if files.contains_key(path) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+
+        let data = Contents::default();
+        files.insert(path.to_path_buf(), data.clone());
+
+        Ok(MemoryFile::new(data))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut files = lock_ignore_poison(&self.files);
+        let data = files.remove(from).ok_or_else(Self::not_found)?;
+        files.insert(to.to_path_buf(), data);
+
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let mut files = lock_ignore_poison(&self.files);
+        files.remove(path).map(drop).ok_or_else(Self::not_found)
+    }
+
+    fn lock(&self, path: &Path) -> io::Result<MemoryLock> {
+        let mut locks = lock_ignore_poison(&self.locks);
+
+        // Same observable behaviour as the lockfile already existing on disk
+        if !locks.insert(path.to_path_buf()) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+
+        Ok(MemoryLock {
+            path: path.to_path_buf(),
+            locks: self.locks.clone(),
+        })
+    }
+
+    fn create_dir_all(&self, _path: &Path, _mode: u32) -> io::Result<()> {
+        // Directories are implicit
+        Ok(())
+    }
+}
+
+pub struct MemoryFile {
+    data: Contents,
+    position: u64,
+}
+
+impl MemoryFile {
+    fn new(data: Contents) -> Self {
+        Self { data, position: 0 }
+    }
+
+    /// A file not reachable through any `MemoryStorage`
+    pub fn detached(bytes: Vec<u8>) -> Self {
+        Self::new(Arc::new(Mutex::new(bytes)))
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        lock_ignore_poison(&self.data).clone()
+    }
+}
+
+impl Read for MemoryFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = lock_ignore_poison(&self.data);
+
+        let start = (self.position as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut data = lock_ignore_poison(&self.data);
+
+        let start = self.position as usize;
+        let end = start + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+
+        self.position = end as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryFile {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let len = lock_ignore_poison(&self.data).len() as i64;
+
+        let position = match pos {
+            io::SeekFrom::Start(offset) => offset as i64,
+            io::SeekFrom::End(offset) => len + offset,
+            io::SeekFrom::Current(offset) => self.position as i64 + offset,
+        };
+
+        if position < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative position",
+            ));
+        }
+
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+impl StorageFile for MemoryFile {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        lock_ignore_poison(&self.data).resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn is_regular(&self) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    fn set_mode(&mut self, _mode: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn modified(&self) -> io::Result<Option<SystemTime>> {
+        Ok(None)
+    }
+}
+
+pub struct MemoryLock {
+    path: PathBuf,
+    locks: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl LockGuard for MemoryLock {
+    fn still_held(&self) -> io::Result<bool> {
+        // Memory locks can't be broken
+        Ok(true)
+    }
+}
+
+impl Drop for MemoryLock {
+    fn drop(&mut self) {
+        lock_ignore_poison(&self.locks).remove(&self.path);
+    }
+}
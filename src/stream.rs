@@ -0,0 +1,137 @@
+use std::{
+    collections::HashSet,
+    io::{self, Read, Write},
+};
+
+use crate::{
+    compat::CompatOptions,
+    encoding::{Entry, Interner, read_entry},
+    key::EntryKey,
+};
+
+/// Pulls entries one at a time from any reader, e.g. a pipe.
+/// As an iterator, stops after the first error
+pub struct AuthorityReader<R> {
+    reader: R,
+    interner: Interner,
+    failed: bool,
+}
+
+impl<R: Read> AuthorityReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            interner: Interner::new(),
+            failed: false,
+        }
+    }
+
+    /// None at the end of the input
+    pub fn read_entry(&mut self) -> io::Result<Option<Entry>> {
+        read_entry(&mut self.reader, &mut self.interner)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Iterator for AuthorityReader<R> {
+    type Item = io::Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let result = self.read_entry();
+        self.failed = result.is_err();
+        result.transpose()
+    }
+}
+
+/// Pushes entries one at a time to any writer
+pub struct AuthorityWriter<W> {
+    writer: W,
+    compat: CompatOptions,
+}
+
+impl<W: Write> AuthorityWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            compat: CompatOptions::default(),
+        }
+    }
+
+    pub fn compat(mut self, compat: CompatOptions) -> Self {
+        self.compat = compat;
+        self
+    }
+
+    pub fn write_entry(&mut self, entry: &Entry) -> io::Result<()> {
+        self.compat.apply(entry).write_to(&mut self.writer)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Concatenates authorities into one writer, dropping entries whose key
+/// was already written. Earlier sources win, as they would shadow later ones anyway.
+///
+/// Only keys are remembered, so memory stays bounded by the number of distinct entries
+/// rather than their cookies
+pub struct AppendWriter<W> {
+    writer: AuthorityWriter<W>,
+    seen: HashSet<EntryKey>,
+}
+
+impl<W: Write> AppendWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: AuthorityWriter::new(writer),
+            seen: HashSet::new(),
+        }
+    }
+
+    pub fn compat(mut self, compat: CompatOptions) -> Self {
+        self.writer = self.writer.compat(compat);
+        self
+    }
+
+    /// Returns false if the entry was a duplicate and skipped
+    pub fn write_entry(&mut self, entry: &Entry) -> io::Result<bool> {
+        if self.seen.contains(&entry.key()) {
+            return Ok(false);
+        }
+
+        self.writer.write_entry(entry)?;
+        self.seen.insert(entry.key());
+        Ok(true)
+    }
+
+    /// Streams every entry of `source` through, returning how many were written
+    pub fn append_from<R: Read>(&mut self, source: R) -> io::Result<usize> {
+        let mut written = 0;
+
+        for entry in AuthorityReader::new(source) {
+            written += self.write_entry(&entry?)? as usize;
+        }
+
+        Ok(written)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{encoding::Entry, hash::hash_entry};
+
+/// Creation times of entries, kept in a `<file>-t` sidecar next to the authority,
+/// since the format itself has no room for them.
+///
+/// One `<entry hash> <unix seconds>` line per entry
+pub(crate) struct Timestamps {
+    path: PathBuf,
+    created: HashMap<u64, u64>,
+}
+
+impl Timestamps {
+    pub fn load(xauth_path: &Path) -> io::Result<Self> {
+        let mut path = xauth_path.as_os_str().to_os_string();
+        path.push("-t");
+        let path = PathBuf::from(path);
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        // Best effort: a damaged line only loses that entry's time
+        let created = contents
+            .lines()
+            .filter_map(|line| {
+                let (hash, secs) = line.split_once(' ')?;
+                Some((u64::from_str_radix(hash, 16).ok()?, secs.parse().ok()?))
+            })
+            .collect();
+
+        Ok(Self { path, created })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&self.path)?;
+
+        let mut contents = String::new();
+        for (hash, secs) in &self.created {
+            contents.push_str(&format!("{hash:016x} {secs}\n"));
+        }
+
+        file.write_all(contents.as_bytes())
+    }
+
+    pub fn created(&self, entry: &Entry) -> Option<SystemTime> {
+        let secs = self.created.get(&hash_entry(entry))?;
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(*secs))
+    }
+
+    /// Stamps entries seen for the first time with the current time
+    pub fn record<'a>(&mut self, entries: impl IntoIterator<Item = &'a Entry>) {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        for entry in entries {
+            self.created.entry(hash_entry(entry)).or_insert(now);
+        }
+    }
+
+    /// Forgets entries no longer in the file
+    pub fn retain<'a>(&mut self, entries: impl IntoIterator<Item = &'a Entry>) {
+        let kept: HashMap<u64, u64> = entries
+            .into_iter()
+            .map(hash_entry)
+            .filter_map(|hash| Some((hash, *self.created.get(&hash)?)))
+            .collect();
+
+        self.created = kept;
+    }
+}
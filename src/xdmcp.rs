@@ -0,0 +1,74 @@
+use std::fmt;
+
+use crate::{
+    Cookie, XDM_AUTHORIZATION_1, audit::Redacted, encoding::Entry, key::EntryKey, shared_name,
+};
+
+/// Authorization an XDMCP manager sends in its ACCEPT packet,
+/// with the matching entry for the session's authority file
+#[derive(Clone)]
+pub struct XdmcpAuthorization {
+    pub name: String,
+    pub data: Vec<u8>,
+    /// For the display at the key passed in, e.g. from `EntryKey::from_socket_addr`
+    pub entry: Entry,
+}
+
+impl fmt::Debug for XdmcpAuthorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("XdmcpAuthorization")
+            .field("name", &self.name)
+            .field("data", &Redacted(&self.data))
+            .field("entry", &self.entry)
+            .finish()
+    }
+}
+
+fn entry_for(key: EntryKey, auth_name: &str, auth_data: Vec<u8>) -> Entry {
+    Entry::from_parts(
+        key.family,
+        key.address,
+        key.display_number,
+        shared_name(auth_name),
+        auth_data,
+    )
+}
+
+impl XdmcpAuthorization {
+    /// MIT-MAGIC-COOKIE-1: the cookie is sent as-is, so the channel must be trusted
+    pub fn magic_cookie(cookie: &Cookie, key: EntryKey) -> Self {
+        let (name, data) = cookie.raw_data();
+
+        Self {
+            entry: entry_for(key, &name, data.clone()),
+            name,
+            data,
+        }
+    }
+
+    /// XDM-AUTHORIZATION-1, framed the way xdm does it.
+    ///
+    /// `rho` is from the display's XDM-AUTHENTICATION-1 exchange, `session_key` random bytes.
+    /// `wrap` encrypts one block with DES under the display's shared key,
+    /// this crate doesn't carry a DES implementation
+    pub fn xdm_authorization(
+        rho: [u8; 8],
+        mut session_key: [u8; 8],
+        key: EntryKey,
+        wrap: impl FnOnce([u8; 8]) -> [u8; 8],
+    ) -> Self {
+        // DES only uses 56 bits, xdm zeroes the first byte
+        session_key[0] = 0;
+
+        // Clients send rho and a timestamp encrypted with the session key,
+        // which the server checks with both halves of its entry
+        let mut file_data = rho.to_vec();
+        file_data.extend_from_slice(&session_key);
+
+        Self {
+            name: XDM_AUTHORIZATION_1.to_string(),
+            data: wrap(session_key).to_vec(),
+            entry: entry_for(key, XDM_AUTHORIZATION_1, file_data),
+        }
+    }
+}